@@ -0,0 +1,94 @@
+use crate::price_source::{PriceQuote, PriceSource};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use reqwest::Client;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Ticker24h {
+    last_price: Decimal,
+    /// End of the 24 hour window, in milliseconds since the epoch
+    close_time: u64,
+}
+
+impl Ticker24h {
+    fn quote(&self) -> PriceQuote {
+        PriceQuote {
+            price: self.last_price,
+            timestamp: UNIX_EPOCH + Duration::from_millis(self.close_time),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BinanceClient {
+    client: Client,
+}
+
+impl Default for BinanceClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BinanceClient {
+    pub fn new() -> Self {
+        Self {
+            client: Client::new(),
+        }
+    }
+
+    /// Last trade price for a symbol, e.g. "ETHUSDT", stamped with the time
+    /// Binance last updated its 24 hour statistics
+    pub async fn get_quote(&self, symbol: &str) -> Result<PriceQuote> {
+        let url = format!(
+            "https://api.binance.com/api/v3/ticker/24hr?symbol={}",
+            symbol
+        );
+        let resp = self.client.get(&url).send().await?;
+        if !resp.status().is_success() {
+            return Err(anyhow!("Binance HTTP error: {}", resp.status()));
+        }
+        let ticker: Ticker24h = resp.json().await?;
+        Ok(ticker.quote())
+    }
+}
+
+#[async_trait]
+impl PriceSource for BinanceClient {
+    fn name(&self) -> &str {
+        "binance"
+    }
+
+    async fn price(&self, pair: &str) -> Result<PriceQuote> {
+        self.get_quote(pair).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn quote_is_stamped_with_close_time() {
+        let ticker: Ticker24h = serde_json::from_str(
+            r#"{"symbol":"ETHUSDT","priceChange":"21.01","priceChangePercent":"0.847",
+            "weightedAvgPrice":"2490.33","prevClosePrice":"2480.00","lastPrice":"2501.01",
+            "lastQty":"0.0172","bidPrice":"2501.00","bidQty":"3.1","askPrice":"2501.02",
+            "askQty":"12.0","openPrice":"2480.00","highPrice":"2520.00","lowPrice":"2455.12",
+            "volume":"412012.7","quoteVolume":"1026012345.1","openTime":1715685643013,
+            "closeTime":1715772043013,"firstId":1,"lastId":12870,"count":12870}"#,
+        )
+        .unwrap();
+        let quote = ticker.quote();
+        assert_eq!(quote.price, dec!(2501.01));
+        assert_eq!(
+            quote.timestamp,
+            UNIX_EPOCH + Duration::from_millis(1_715_772_043_013)
+        );
+    }
+}
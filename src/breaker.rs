@@ -0,0 +1,180 @@
+use rust_decimal::Decimal;
+use std::time::{Duration, Instant};
+
+/// Whether a pair may trade, given how far its pool strayed from the reference
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BreakerState {
+    #[default]
+    Armed,
+    /// Diverged past the halt threshold. `resume_generation` is the manual
+    /// resume counter at the time, so any later resume lifts the halt
+    Halted {
+        calm_since: Option<Instant>,
+        resume_generation: u64,
+    },
+    /// Resumed by hand while still diverged: trades until the divergence
+    /// drops below the threshold, then arms again
+    Overridden,
+}
+
+/// Why a halted pair trades again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resumed {
+    Manually,
+    AfterCooldown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Trade,
+    /// The divergence just crossed the threshold
+    Tripped,
+    Halted,
+    Resumed(Resumed),
+}
+
+/// Stops a pair whose pool diverges so far from the reference price that a
+/// bad oracle, a depeg or an exploit is more likely than an arbitrage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DivergenceBreaker {
+    pub halt_deviation_bps: Decimal,
+    /// How long the divergence must stay below the threshold before a
+    /// halted pair resumes on its own
+    pub cooldown: Duration,
+}
+
+impl DivergenceBreaker {
+    pub fn check(
+        &self,
+        state: &mut BreakerState,
+        deviation_bps: Decimal,
+        now: Instant,
+        resume_generation: u64,
+    ) -> Verdict {
+        let diverged = deviation_bps >= self.halt_deviation_bps;
+        match *state {
+            BreakerState::Armed if diverged => {
+                *state = BreakerState::Halted {
+                    calm_since: None,
+                    resume_generation,
+                };
+                Verdict::Tripped
+            }
+            BreakerState::Armed => Verdict::Trade,
+            BreakerState::Halted {
+                resume_generation: halted_at,
+                ..
+            } if resume_generation != halted_at => {
+                *state = if diverged {
+                    BreakerState::Overridden
+                } else {
+                    BreakerState::Armed
+                };
+                Verdict::Resumed(Resumed::Manually)
+            }
+            BreakerState::Halted {
+                calm_since,
+                resume_generation,
+            } => {
+                let calm_since = match calm_since {
+                    _ if diverged => None,
+                    Some(since) => Some(since),
+                    None => Some(now),
+                };
+                if calm_since.is_some_and(|since| now.duration_since(since) >= self.cooldown) {
+                    *state = BreakerState::Armed;
+                    return Verdict::Resumed(Resumed::AfterCooldown);
+                }
+                *state = BreakerState::Halted {
+                    calm_since,
+                    resume_generation,
+                };
+                Verdict::Halted
+            }
+            BreakerState::Overridden => {
+                if !diverged {
+                    *state = BreakerState::Armed;
+                }
+                Verdict::Trade
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    const BREAKER: DivergenceBreaker = DivergenceBreaker {
+        halt_deviation_bps: dec!(3000),
+        cooldown: Duration::from_secs(600),
+    };
+
+    #[test]
+    fn resumes_after_calm_cooldown() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut state = BreakerState::default();
+
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(100), at(0), 0),
+            Verdict::Trade
+        );
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(3500), at(10), 0),
+            Verdict::Tripped
+        );
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(200), at(20), 0),
+            Verdict::Halted
+        );
+        // Diverging again restarts the cooldown
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(3000), at(400), 0),
+            Verdict::Halted
+        );
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(200), at(500), 0),
+            Verdict::Halted
+        );
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(200), at(1000), 0),
+            Verdict::Halted
+        );
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(200), at(1100), 0),
+            Verdict::Resumed(Resumed::AfterCooldown)
+        );
+        assert_eq!(state, BreakerState::Armed);
+    }
+
+    #[test]
+    fn manual_resume_overrides_until_divergence_clears() {
+        let now = Instant::now();
+        let mut state = BreakerState::default();
+
+        // A resume sent before the halt doesn't lift it
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(4000), now, 1),
+            Verdict::Tripped
+        );
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(4000), now, 1),
+            Verdict::Halted
+        );
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(4000), now, 2),
+            Verdict::Resumed(Resumed::Manually)
+        );
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(4000), now, 2),
+            Verdict::Trade
+        );
+        assert_eq!(BREAKER.check(&mut state, dec!(100), now, 2), Verdict::Trade);
+        assert_eq!(
+            BREAKER.check(&mut state, dec!(4000), now, 2),
+            Verdict::Tripped
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use crate::price_source::{PriceQuote, PriceSource};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::DateTime;
+use reqwest::Client;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::time::SystemTime;
+
+#[derive(Debug, Deserialize)]
+struct ProductTicker {
+    price: Decimal,
+    /// Time of the last trade, RFC 3339
+    time: String,
+}
+
+impl ProductTicker {
+    fn quote(&self) -> Result<PriceQuote> {
+        let traded_at = DateTime::parse_from_rfc3339(&self.time)
+            .map_err(|e| anyhow!("Invalid Coinbase trade time {}: {}", self.time, e))?;
+        Ok(PriceQuote {
+            price: self.price,
+            timestamp: SystemTime::from(traded_at),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct CoinbaseClient {
+    client: Client,
+}
+
+impl Default for CoinbaseClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CoinbaseClient {
+    pub fn new() -> Self {
+        // Coinbase Exchange rejects requests without a User-Agent
+        let client = Client::builder()
+            .user_agent(concat!("hyperion_dex_bot/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("Failed to create HTTP client");
+        Self { client }
+    }
+
+    /// Last trade price for a product, e.g. "ETH-USD", stamped with the
+    /// time of that trade
+    pub async fn get_quote(&self, product: &str) -> Result<PriceQuote> {
+        let url = format!(
+            "https://api.exchange.coinbase.com/products/{}/ticker",
+            product
+        );
+        let resp = self.client.get(&url).send().await?;
+        if !resp.status().is_success() {
+            return Err(anyhow!("Coinbase HTTP error: {}", resp.status()));
+        }
+        let ticker: ProductTicker = resp.json().await?;
+        ticker.quote()
+    }
+}
+
+#[async_trait]
+impl PriceSource for CoinbaseClient {
+    fn name(&self) -> &str {
+        "coinbase"
+    }
+
+    async fn price(&self, pair: &str) -> Result<PriceQuote> {
+        self.get_quote(pair).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn quote_is_stamped_with_trade_time() {
+        let ticker: ProductTicker = serde_json::from_str(
+            r#"{"ask":"2501.12","bid":"2500.98","volume":"41236.9","trade_id":511232121,
+            "price":"2501.05","size":"0.0172","time":"2024-05-15T11:20:43.013486Z",
+            "rfq_volume":"12.3"}"#,
+        )
+        .unwrap();
+        let quote = ticker.quote().unwrap();
+        assert_eq!(quote.price, dec!(2501.05));
+        assert_eq!(
+            quote.timestamp,
+            UNIX_EPOCH + Duration::from_micros(1_715_772_043_013_486)
+        );
+    }
+}
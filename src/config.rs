@@ -1,7 +1,9 @@
+use crate::kraken_ws;
 use alloy::primitives::Address;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -14,6 +16,127 @@ pub enum ConfigError {
     ValidationError(String),
 }
 
+/// Which Kraken ticker field is used as the price
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KrakenPriceMode {
+    /// Last trade price
+    #[default]
+    Last,
+    /// Midpoint between best bid and best ask
+    Mid,
+    /// 24 hour volume weighted average price
+    Vwap,
+    /// Average of the last `rolling_window` last trade prices seen by the bot
+    Rolling,
+}
+
+fn default_rolling_window() -> usize {
+    12
+}
+
+/// Where a pair's reference price comes from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum PriceSourceConfig {
+    /// Kraken ticker, e.g. "ETHUSD"
+    Kraken {
+        pair: String,
+        #[serde(default)]
+        reverse: bool,
+        #[serde(default)]
+        mode: KrakenPriceMode,
+        /// Number of samples averaged in `rolling` mode
+        #[serde(default = "default_rolling_window")]
+        rolling_window: usize,
+        /// WebSocket v2 symbol, e.g. "ETH/USD". Derived from `pair` if not set
+        #[serde(default)]
+        ws_symbol: Option<String>,
+    },
+    /// Binance symbol, e.g. "ETHUSDT"
+    Binance {
+        pair: String,
+        #[serde(default)]
+        reverse: bool,
+    },
+    /// Coinbase product, e.g. "ETH-USD"
+    Coinbase {
+        pair: String,
+        #[serde(default)]
+        reverse: bool,
+    },
+    /// Fixed, manually maintained price
+    Static { price: Decimal },
+    /// Product of several legs, e.g. BTCUSD and inverted ETHUSD for BTC/ETH.
+    /// Each leg is inverted with its own `reverse` flag.
+    Cross { legs: Vec<PriceSourceConfig> },
+    /// Median of several sources, discarding quotes too far from it
+    Median {
+        sources: Vec<PriceSourceConfig>,
+        /// Quotes further than this from the median are rejected, in basis points
+        max_spread_bps: u32,
+        /// Minimum number of agreeing quotes required to produce a price
+        quorum: usize,
+    },
+}
+
+impl PriceSourceConfig {
+    /// Kraken WebSocket symbols this source reads, including nested sources.
+    /// Fails if a symbol is neither set nor derivable from the REST pair.
+    pub fn kraken_ws_symbols(&self) -> Result<Vec<String>, String> {
+        match self {
+            PriceSourceConfig::Kraken {
+                pair, ws_symbol, ..
+            } => ws_symbol
+                .clone()
+                .or_else(|| kraken_ws::ws_symbol(pair))
+                .map(|symbol| vec![symbol])
+                .ok_or_else(|| {
+                    format!(
+                        "cannot derive a Kraken WebSocket symbol from {}, set it explicitly",
+                        pair
+                    )
+                }),
+            PriceSourceConfig::Cross { legs: sources }
+            | PriceSourceConfig::Median { sources, .. } => sources
+                .iter()
+                .map(|source| source.kraken_ws_symbols())
+                .collect::<Result<Vec<_>, _>>()
+                .map(|symbols| symbols.concat()),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            PriceSourceConfig::Kraken { rolling_window, .. } if *rolling_window == 0 => {
+                Err("rolling_window must be at least 1".to_string())
+            }
+            PriceSourceConfig::Static { price } if *price <= Decimal::ZERO => {
+                Err("static price must be positive".to_string())
+            }
+            PriceSourceConfig::Cross { legs } => {
+                if legs.is_empty() {
+                    return Err("cross price needs at least one leg".to_string());
+                }
+                legs.iter().try_for_each(|leg| leg.validate())
+            }
+            PriceSourceConfig::Median {
+                sources, quorum, ..
+            } => {
+                if *quorum == 0 || *quorum > sources.len() {
+                    return Err(format!(
+                        "median quorum must be between 1 and the number of sources ({})",
+                        sources.len()
+                    ));
+                }
+                sources.iter().try_for_each(|source| source.validate())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PoolConfig {
     pub name: String,
@@ -24,17 +147,306 @@ pub struct PoolConfig {
     /// Address of the UniswapV2Pair for this pair
     pub pair_address: Address,
     pub kuma_push_id: String,
-    /// Kraken ticker pair, e.g. "ETHUSD"
+    /// Kraken ticker pair, e.g. "ETHUSD". Used when `price_source` is not set
+    #[serde(default)]
     pub kraken_pair: String,
     /// If true, invert the fetched Kraken price (1/price)
     #[serde(default)]
     pub reverse_kraken_pair: bool,
+    /// Stop trading if the reference price is older than this many seconds
+    #[serde(default = "default_max_price_age_secs")]
+    pub max_price_age_secs: u64,
+    /// Which Kraken ticker field to use for `kraken_pair`
+    #[serde(default)]
+    pub kraken_price_mode: KrakenPriceMode,
+    /// Number of samples averaged when `kraken_price_mode` is `rolling`
+    #[serde(default = "default_rolling_window")]
+    pub kraken_rolling_window: usize,
+    /// WebSocket v2 symbol for `kraken_pair`, e.g. "ETH/USD". Derived from
+    /// `kraken_pair` if not set
+    #[serde(default)]
+    pub kraken_ws_symbol: Option<String>,
+    /// Reference price source, overriding `kraken_pair`
+    #[serde(default)]
+    pub price_source: Option<PriceSourceConfig>,
+    /// LP fee charged by the pair, in basis points (UniswapV2 default is 30)
+    #[serde(default = "default_fee_bps")]
+    pub fee_bps: u32,
+    /// Minimum pool/reference price deviation, in basis points, before a swap is sent
+    #[serde(default = "default_deviation_threshold_bps")]
+    pub deviation_threshold_bps: u32,
+    /// How far past the reference price to move the pool, in basis points
+    #[serde(default = "default_overshoot_bps")]
+    pub overshoot_bps: u32,
+    /// Maximum accepted slippage against the `getAmountsOut` quote, in basis points
+    #[serde(default = "default_slippage_bps")]
+    pub slippage_bps: u32,
+    /// Price of the chain's native gas token in token0 units, used to charge
+    /// gas against the expected profit of a swap. Required unless token0 or
+    /// token1 is `wrapped_native`, in which case the pair itself prices gas
+    #[serde(default)]
+    pub native_price_source: Option<PriceSourceConfig>,
+    /// Minimum expected profit after gas, in basis points of the swap input
+    #[serde(default)]
+    pub min_profit_bps: u32,
+    /// Largest amount of token0 sold in one swap, in human units. Larger
+    /// rebalances are spread over several rounds
+    #[serde(default)]
+    pub max_trade_size0: Option<Decimal>,
+    /// Largest amount of token1 sold in one swap, in human units
+    #[serde(default)]
+    pub max_trade_size1: Option<Decimal>,
+    /// Largest share of the wallet's input token balance sold in one swap,
+    /// between 0 and 1
+    #[serde(default)]
+    pub max_balance_fraction: Option<Decimal>,
+    /// Most token0 sold over any 24 hours, in human units
+    #[serde(default)]
+    pub max_daily_volume0: Option<Decimal>,
+    /// Most token1 sold over any 24 hours, in human units
+    #[serde(default)]
+    pub max_daily_volume1: Option<Decimal>,
+    /// Pool/reference price deviation, in basis points, at which the pair
+    /// stops trading and reports Down until resumed
+    #[serde(default)]
+    pub halt_deviation_bps: Option<u32>,
+    /// Seconds the deviation must stay below `halt_deviation_bps` before a
+    /// halted pair resumes on its own
+    #[serde(default = "default_halt_cooldown_secs")]
+    pub halt_cooldown_secs: u64,
+}
+
+impl PoolConfig {
+    /// The configured reference price source, falling back to `kraken_pair`
+    pub fn price_source(&self) -> PriceSourceConfig {
+        match &self.price_source {
+            Some(source) => source.clone(),
+            None => PriceSourceConfig::Kraken {
+                pair: self.kraken_pair.clone(),
+                reverse: self.reverse_kraken_pair,
+                mode: self.kraken_price_mode,
+                rolling_window: self.kraken_rolling_window,
+                ws_symbol: self.kraken_ws_symbol.clone(),
+            },
+        }
+    }
+
+    /// Kraken WebSocket symbols of the reference and native price sources
+    pub fn kraken_ws_symbols(&self) -> Result<Vec<String>, String> {
+        let mut symbols = self.price_source().kraken_ws_symbols()?;
+        if let Some(native) = &self.native_price_source {
+            symbols.extend(
+                native
+                    .kraken_ws_symbols()
+                    .map_err(|e| format!("native_price_source: {}", e))?,
+            );
+        }
+        Ok(symbols)
+    }
+}
+
+fn default_max_price_age_secs() -> u64 {
+    60
+}
+
+fn default_fee_bps() -> u32 {
+    30
+}
+
+fn default_deviation_threshold_bps() -> u32 {
+    50
+}
+
+fn default_overshoot_bps() -> u32 {
+    1
+}
+
+fn default_slippage_bps() -> u32 {
+    50
+}
+
+fn default_halt_cooldown_secs() -> u64 {
+    900
+}
+
+fn default_swap_deadline_secs() -> u64 {
+    120
+}
+
+fn default_receipt_timeout_secs() -> u64 {
+    180
+}
+
+fn default_stuck_after_blocks() -> u64 {
+    5
+}
+
+fn default_gas_bump_percent() -> u64 {
+    15
+}
+
+fn default_max_replacements() -> u32 {
+    3
+}
+
+/// Kraken WebSocket ticker feed shared by all pairs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KrakenWsConfig {
+    #[serde(default = "default_kraken_ws_url")]
+    pub url: String,
+    /// Cached tickers older than this are treated as unavailable
+    #[serde(default = "default_kraken_ws_stale_after_secs")]
+    pub stale_after_secs: u64,
+}
+
+fn default_kraken_ws_url() -> String {
+    kraken_ws::KRAKEN_WS_URL.to_string()
+}
+
+fn default_kraken_ws_stale_after_secs() -> u64 {
+    60
+}
+
+/// Which chain events start a pair round early when `ws_rpc_url` is set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerMode {
+    /// The pair's `Sync` event, emitted whenever its reserves change
+    #[default]
+    Sync,
+    /// Every new block header
+    NewHeads,
+}
+
+/// How transaction fees are priced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GasMode {
+    /// Type 0 transactions with a single gas price from `eth_gasPrice`
+    Legacy,
+    /// Type 2 transactions priced from `eth_feeHistory`
+    #[default]
+    Eip1559,
+}
+
+/// Gas pricing strategy shared by every transaction the bot sends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GasConfig {
+    #[serde(default)]
+    pub mode: GasMode,
+    /// Percentile of recent priority fees to pay, in EIP-1559 mode
+    #[serde(default = "default_priority_fee_percentile")]
+    pub priority_fee_percentile: f64,
+    /// Number of recent blocks the priority fee is sampled from
+    #[serde(default = "default_fee_history_blocks")]
+    pub fee_history_blocks: u64,
+    /// Upper bound on the gas price or max fee per gas, in wei
+    #[serde(default)]
+    pub max_fee_per_gas: Option<u128>,
+    /// Gas limit for every transaction instead of estimating it
+    #[serde(default)]
+    pub gas_limit: Option<u64>,
+    /// Refuse to trade while the base fee (or gas price) is above this, in wei
+    #[serde(default)]
+    pub max_network_fee: Option<u128>,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            mode: GasMode::default(),
+            priority_fee_percentile: default_priority_fee_percentile(),
+            fee_history_blocks: default_fee_history_blocks(),
+            max_fee_per_gas: None,
+            gas_limit: None,
+            max_network_fee: None,
+        }
+    }
+}
+
+fn default_priority_fee_percentile() -> f64 {
+    50.0
+}
+
+fn default_fee_history_blocks() -> u64 {
+    10
+}
+
+/// Backoff applied when RPC or price source calls fail
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Delay after the first failure
+    #[serde(default = "default_initial_delay_ms")]
+    pub initial_delay_ms: u64,
+    /// Upper bound on the delay between attempts
+    #[serde(default = "default_max_delay_secs")]
+    pub max_delay_secs: u64,
+    /// Growth of the delay with each consecutive failure
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+    /// Random spread applied to each delay, as a fraction of it
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+    /// Consecutive failures after which the pair stops calling out for a while
+    #[serde(default = "default_circuit_threshold")]
+    pub circuit_threshold: u32,
+    /// How long the circuit stays open
+    #[serde(default = "default_circuit_open_secs")]
+    pub circuit_open_secs: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: default_initial_delay_ms(),
+            max_delay_secs: default_max_delay_secs(),
+            multiplier: default_multiplier(),
+            jitter: default_jitter(),
+            circuit_threshold: default_circuit_threshold(),
+            circuit_open_secs: default_circuit_open_secs(),
+        }
+    }
+}
+
+fn default_initial_delay_ms() -> u64 {
+    1000
+}
+
+fn default_max_delay_secs() -> u64 {
+    60
+}
+
+fn default_multiplier() -> f64 {
+    2.0
+}
+
+fn default_jitter() -> f64 {
+    0.2
+}
+
+fn default_circuit_threshold() -> u32 {
+    10
+}
+
+fn default_circuit_open_secs() -> u64 {
+    300
+}
+
+fn default_native_decimals() -> u8 {
+    18
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Ethereum RPC URL
     pub rpc_url: String,
+    /// WebSocket RPC URL used to subscribe to chain events. Pairs are polled
+    /// on a fixed interval if not set
+    #[serde(default)]
+    pub ws_rpc_url: Option<String>,
+    /// Events that start a pair round early over `ws_rpc_url`
+    #[serde(default)]
+    pub round_trigger: TriggerMode,
     /// Address of Uniswap V2 router
     pub uniswap_v2_router: Address,
     /// Address of Mortal Coin game contract
@@ -45,6 +457,41 @@ pub struct Config {
     pub general_push_id: String,
     /// Private key for the trading account (hex string without 0x prefix)
     pub private_key: String,
+    /// Seconds after the latest block timestamp before a submitted swap expires
+    #[serde(default = "default_swap_deadline_secs")]
+    pub swap_deadline_secs: u64,
+    /// How long to wait for a submitted transaction to be mined before giving up on it
+    #[serde(default = "default_receipt_timeout_secs")]
+    pub receipt_timeout_secs: u64,
+    /// Blocks a transaction may stay pending before it is replaced with higher fees
+    #[serde(default = "default_stuck_after_blocks")]
+    pub stuck_after_blocks: u64,
+    /// Percentage by which each replacement raises the fees of a stuck transaction
+    #[serde(default = "default_gas_bump_percent")]
+    pub gas_bump_percent: u64,
+    /// How many times a stuck transaction is replaced before giving up on it
+    #[serde(default = "default_max_replacements")]
+    pub max_replacements: u32,
+    /// Read Kraken prices from a shared WebSocket feed instead of polling REST
+    #[serde(default)]
+    pub kraken_ws: Option<KrakenWsConfig>,
+    /// Transaction fee pricing
+    #[serde(default)]
+    pub gas: GasConfig,
+    /// Backoff for failed RPC and price source calls
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Wrapped native gas token, e.g. WMETIS. Pairs trading it value gas at
+    /// their own price instead of needing a `native_price_source`
+    #[serde(default)]
+    pub wrapped_native: Option<Address>,
+    /// Decimals of the native gas token
+    #[serde(default = "default_native_decimals")]
+    pub native_decimals: u8,
+    /// SQLite database every round of every pair is recorded in. Nothing is
+    /// recorded if not set
+    #[serde(default)]
+    pub journal_path: Option<PathBuf>,
     /// List of pairs to trade
     pub pairs: Vec<PoolConfig>,
 }
@@ -52,8 +499,12 @@ pub struct Config {
 impl Config {
     /// Load configuration from a TOML file
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
-        let content = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Parse and validate configuration from TOML text
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(content)?;
 
         // Validate configuration
         if config.rpc_url.is_empty() {
@@ -68,12 +519,376 @@ impl Config {
             ));
         }
 
+        if config.stuck_after_blocks == 0 {
+            return Err(ConfigError::ValidationError(
+                "stuck_after_blocks must be at least 1".to_string(),
+            ));
+        }
+
+        // Nodes reject replacements that raise fees by less than 10%
+        if config.gas_bump_percent < 10 {
+            return Err(ConfigError::ValidationError(
+                "gas_bump_percent must be at least 10".to_string(),
+            ));
+        }
+
+        if !(0.0..=100.0).contains(&config.gas.priority_fee_percentile) {
+            return Err(ConfigError::ValidationError(
+                "gas.priority_fee_percentile must be between 0 and 100".to_string(),
+            ));
+        }
+
+        if !(1..=1024).contains(&config.gas.fee_history_blocks) {
+            return Err(ConfigError::ValidationError(
+                "gas.fee_history_blocks must be between 1 and 1024".to_string(),
+            ));
+        }
+
+        if config.retry.multiplier < 1.0 {
+            return Err(ConfigError::ValidationError(
+                "retry.multiplier must be at least 1".to_string(),
+            ));
+        }
+
+        if !(0.0..1.0).contains(&config.retry.jitter) {
+            return Err(ConfigError::ValidationError(
+                "retry.jitter must be between 0 and 1".to_string(),
+            ));
+        }
+
+        if config.retry.circuit_threshold == 0 {
+            return Err(ConfigError::ValidationError(
+                "retry.circuit_threshold must be at least 1".to_string(),
+            ));
+        }
+
         if config.pairs.is_empty() {
             return Err(ConfigError::ValidationError(
                 "At least one pool must be specified".to_string(),
             ));
         }
 
+        for pair in &config.pairs {
+            if pair.price_source.is_none() && pair.kraken_pair.is_empty() {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: either kraken_pair or price_source must be set",
+                    pair.name
+                )));
+            }
+            if let Err(e) = pair.price_source().validate() {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: {}",
+                    pair.name, e
+                )));
+            }
+            if let Some(Err(e)) = pair.native_price_source.as_ref().map(|s| s.validate()) {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: native_price_source: {}",
+                    pair.name, e
+                )));
+            }
+            let trades_native = config
+                .wrapped_native
+                .is_some_and(|wrapped| wrapped == pair.token0 || wrapped == pair.token1);
+            if pair.native_price_source.is_none() && !trades_native {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: native_price_source must be set to charge gas against profit, \
+                     unless token0 or token1 is wrapped_native (see \"Upgrading older \
+                     configurations\" in the README)",
+                    pair.name
+                )));
+            }
+            if config.kraken_ws.is_some() {
+                let symbols = pair.kraken_ws_symbols();
+                if let Err(e) = symbols {
+                    return Err(ConfigError::ValidationError(format!(
+                        "Pair {}: {}",
+                        pair.name, e
+                    )));
+                }
+            }
+            if pair.fee_bps >= 10000 {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: fee_bps must be below 10000",
+                    pair.name
+                )));
+            }
+            if pair.slippage_bps >= 10000 {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: slippage_bps must be below 10000",
+                    pair.name
+                )));
+            }
+            // A zero threshold with zero overshoot pegs the pool exactly
+            if pair.overshoot_bps > 0 && pair.overshoot_bps >= pair.deviation_threshold_bps {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: overshoot_bps must be below deviation_threshold_bps",
+                    pair.name
+                )));
+            }
+            if pair
+                .halt_deviation_bps
+                .is_some_and(|halt| halt <= pair.deviation_threshold_bps)
+            {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: halt_deviation_bps must be above deviation_threshold_bps",
+                    pair.name
+                )));
+            }
+            let caps = [
+                ("max_trade_size0", pair.max_trade_size0),
+                ("max_trade_size1", pair.max_trade_size1),
+                ("max_daily_volume0", pair.max_daily_volume0),
+                ("max_daily_volume1", pair.max_daily_volume1),
+            ];
+            if let Some((field, _)) = caps
+                .iter()
+                .find(|(_, cap)| cap.is_some_and(|cap| cap <= Decimal::ZERO))
+            {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: {} must be positive",
+                    pair.name, field
+                )));
+            }
+            if pair
+                .max_balance_fraction
+                .is_some_and(|fraction| fraction <= Decimal::ZERO || fraction > Decimal::ONE)
+            {
+                return Err(ConfigError::ValidationError(format!(
+                    "Pair {}: max_balance_fraction must be above 0 and at most 1",
+                    pair.name
+                )));
+            }
+        }
+
         Ok(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A valid config with one pair, plus `fields` before the pairs and
+    /// `pair_fields` appended to the pair
+    fn parse_with(fields: &str, pair_fields: &str) -> Result<Config, ConfigError> {
+        Config::from_toml(&format!(
+            r#"
+            rpc_url = "http://localhost:8545"
+            private_key = "abcdef"
+            base_kuma_url = "http://kuma"
+            general_push_id = "pushID"
+            uniswap_v2_router = "0xa1cF48c109f8B5eEe38B406591FE27f11f685a1f"
+            game_contract = "0xDf8730693833b9e27D5999863feBb3028fcc6927"
+            {fields}
+
+            [[pairs]]
+            name = "WETH-USDT"
+            token0 = "0x231dfCFd9dEF41f86f5b7AB77ef0946C0Cda3B1B"
+            min_balance0 = 0
+            token1 = "0x3512B762158FF2caCb0551215618Ec2dF303FDD9"
+            min_balance1 = 0
+            pair_address = "0x9d5868afd7e4DdAEc6Cd6D45de7C268D912bf8a6"
+            kuma_push_id = "pushID"
+            {pair_fields}
+            "#
+        ))
+    }
+
+    /// Makes token0 price the pair's gas
+    const WRAPPED_TOKEN0: &str = r#"wrapped_native = "0x231dfCFd9dEF41f86f5b7AB77ef0946C0Cda3B1B""#;
+
+    fn parse(pair_fields: &str) -> Result<Config, ConfigError> {
+        parse_with(
+            WRAPPED_TOKEN0,
+            &format!("kraken_pair = \"ETHUSD\"\n{pair_fields}"),
+        )
+    }
+
+    fn rejected(pair_fields: &str) -> String {
+        match parse(pair_fields) {
+            Err(ConfigError::ValidationError(message)) => message,
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn overshoot_must_stay_below_threshold() {
+        assert!(parse("deviation_threshold_bps = 50\novershoot_bps = 49").is_ok());
+        assert!(
+            rejected("deviation_threshold_bps = 50\novershoot_bps = 50").contains("overshoot_bps")
+        );
+        // Exact pegging: swap on any deviation and land on the reference price
+        assert!(parse("deviation_threshold_bps = 0\novershoot_bps = 0").is_ok());
+        assert!(
+            rejected("deviation_threshold_bps = 0\novershoot_bps = 1").contains("overshoot_bps")
+        );
+    }
+
+    fn source(fields: &str) -> Result<PriceSourceConfig, ConfigError> {
+        let mut config = parse(&format!("[pairs.price_source]\n{fields}"))?;
+        Ok(config
+            .pairs
+            .remove(0)
+            .price_source
+            .expect("price_source is set"))
+    }
+
+    #[test]
+    fn parses_exchange_sources() {
+        let kraken = source(
+            r#"
+            source = "kraken"
+            pair = "ETHUSD"
+            mode = "rolling"
+            rolling_window = 5
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            kraken,
+            PriceSourceConfig::Kraken {
+                ref pair,
+                reverse: false,
+                mode: KrakenPriceMode::Rolling,
+                rolling_window: 5,
+                ws_symbol: None,
+            } if pair == "ETHUSD"
+        ));
+        assert_eq!(kraken.kraken_ws_symbols(), Ok(vec!["ETH/USD".to_string()]));
+        assert!(
+            rejected(
+                "[pairs.price_source]\nsource = \"kraken\"\npair = \"ETHUSD\"\nrolling_window = 0"
+            )
+            .contains("rolling_window")
+        );
+
+        let binance = source("source = \"binance\"\npair = \"ETHUSDT\"\nreverse = true").unwrap();
+        assert!(matches!(
+            binance,
+            PriceSourceConfig::Binance { ref pair, reverse: true } if pair == "ETHUSDT"
+        ));
+
+        let coinbase = source("source = \"coinbase\"\npair = \"ETH-USD\"").unwrap();
+        assert!(matches!(
+            coinbase,
+            PriceSourceConfig::Coinbase { ref pair, reverse: false } if pair == "ETH-USD"
+        ));
+    }
+
+    #[test]
+    fn gas_must_be_priced() {
+        assert_eq!(parse("").unwrap().native_decimals, 18);
+        let unpriced =
+            |pair_fields: &str| parse_with("", &format!("kraken_pair = \"ETHUSD\"\n{pair_fields}"));
+        assert!(matches!(
+            unpriced(""),
+            Err(ConfigError::ValidationError(message)) if message.contains("native_price_source")
+        ));
+        assert!(
+            unpriced("[pairs.native_price_source]\nsource = \"static\"\nprice = \"0.0004\"")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn kraken_ws_symbol_for_legacy_pair() {
+        let feed = &format!("{WRAPPED_TOKEN0}\n[kraken_ws]");
+        let config = parse_with(feed, "kraken_pair = \"XETHZUSD\"").unwrap();
+        assert_eq!(
+            config.pairs[0].kraken_ws_symbols(),
+            Ok(vec!["ETH/USD".to_string()])
+        );
+
+        // Only needed when the feed is enabled
+        let underivable = "kraken_pair = \"ETHSOL\"";
+        assert!(parse_with(WRAPPED_TOKEN0, underivable).is_ok());
+        assert!(matches!(
+            parse_with(feed, underivable),
+            Err(ConfigError::ValidationError(message)) if message.contains("ETHSOL")
+        ));
+        let config = parse_with(
+            feed,
+            &format!("{underivable}\nkraken_ws_symbol = \"ETH/SOL\""),
+        )
+        .unwrap();
+        assert_eq!(
+            config.pairs[0].kraken_ws_symbols(),
+            Ok(vec!["ETH/SOL".to_string()])
+        );
+    }
+
+    #[test]
+    fn static_price_must_be_positive() {
+        assert!(matches!(
+            source("source = \"static\"\nprice = \"1.5\"").unwrap(),
+            PriceSourceConfig::Static { price } if price == Decimal::new(15, 1)
+        ));
+        for price in ["0", "-1"] {
+            let message = rejected(&format!(
+                "[pairs.price_source]\nsource = \"static\"\nprice = \"{price}\""
+            ));
+            assert!(
+                message.contains("static price must be positive"),
+                "{message}"
+            );
+        }
+    }
+
+    #[test]
+    fn validates_nested_sources() {
+        let cross = source(
+            r#"
+            source = "cross"
+            legs = [
+                { source = "kraken", pair = "XBTUSD" },
+                { source = "kraken", pair = "ETHUSD", reverse = true },
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            cross.kraken_ws_symbols(),
+            Ok(vec!["BTC/USD".to_string(), "ETH/USD".to_string()])
+        );
+        assert!(
+            rejected("[pairs.price_source]\nsource = \"cross\"\nlegs = []")
+                .contains("at least one leg")
+        );
+        assert!(
+            rejected(
+                r#"
+                [pairs.price_source]
+                source = "cross"
+                legs = [{ source = "static", price = "0" }]
+                "#
+            )
+            .contains("static price")
+        );
+
+        let median = |quorum: usize| {
+            source(&format!(
+                r#"
+                source = "median"
+                max_spread_bps = 100
+                quorum = {quorum}
+                sources = [
+                    {{ source = "kraken", pair = "ETHUSD" }},
+                    {{ source = "binance", pair = "ETHUSDT" }},
+                ]
+                "#
+            ))
+        };
+        assert!(matches!(
+            median(2).unwrap(),
+            PriceSourceConfig::Median { ref sources, max_spread_bps: 100, quorum: 2 }
+                if sources.len() == 2
+        ));
+        for quorum in [0, 3] {
+            assert!(matches!(
+                median(quorum),
+                Err(ConfigError::ValidationError(message)) if message.contains("quorum")
+            ));
+        }
+    }
+}
@@ -23,6 +23,8 @@ sol!(
         function decimals() external view returns (uint8);
         function name() external view returns (string memory);
         function symbol() external view returns (string memory);
+
+        event Transfer(address indexed from, address indexed to, uint256 value);
     }
 );
 
@@ -39,5 +41,15 @@ sol!(
         function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
         function token0() external view returns (address);
         function token1() external view returns (address);
+
+        event Swap(
+            address indexed sender,
+            uint amount0In,
+            uint amount1In,
+            uint amount0Out,
+            uint amount1Out,
+            address indexed to
+        );
+        event Sync(uint112 reserve0, uint112 reserve1);
     }
 );
@@ -0,0 +1,139 @@
+use crate::gas::GasPriceTooHighError;
+use crate::tx::{RevertReason, TxError};
+use alloy::primitives::Address;
+use rust_decimal::Decimal;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why a round of the pair loop ended without completing
+#[derive(Debug, Error)]
+pub enum BotError {
+    #[error("Failed to {call}: {source}")]
+    Rpc {
+        call: &'static str,
+        source: anyhow::Error,
+    },
+    #[error("Failed to fetch {what} price from {source_name}: {source}")]
+    PriceUnavailable {
+        what: &'static str,
+        source_name: String,
+        source: anyhow::Error,
+    },
+    /// The oracle answered but its price is not safe to trade on
+    #[error("Refusing to trade: {0}")]
+    PriceRejected(anyhow::Error),
+    #[error("Pool has no liquidity")]
+    NoLiquidity,
+    #[error("{0} cannot be represented")]
+    Unrepresentable(String),
+    #[error("Insufficient {symbol} balance. Top up address {wallet}")]
+    InsufficientBalance { symbol: String, wallet: Address },
+    #[error("Insufficient gas balance. Top up address {wallet}")]
+    InsufficientGas { wallet: Address },
+    #[error("Refusing to trade: {0}")]
+    GasPriceTooHigh(#[from] GasPriceTooHighError),
+    #[error("Approval failed: {0}")]
+    Approval(anyhow::Error),
+    #[error("Swap simulation reverted: {0}")]
+    SimulationReverted(RevertReason),
+    #[error("Failed to submit swap: {0}")]
+    Submission(anyhow::Error),
+    #[error("Swap failed: {0}")]
+    SwapFailed(TxError),
+    #[error(
+        "Trading halted: pool price {pool_price} is {deviation_bps} bps from reference {reference_price}"
+    )]
+    Halted {
+        pool_price: Decimal,
+        reference_price: Decimal,
+        deviation_bps: Decimal,
+    },
+    #[error("Daily volume cap reached, more frees up in {}s", resets_in.as_secs())]
+    VolumeCapReached { resets_in: Duration },
+    /// A new transaction was about to be sent after shutdown began
+    #[error("Shutting down")]
+    ShuttingDown,
+}
+
+impl BotError {
+    /// Adapter for `map_err` on a failed RPC call
+    pub fn rpc<E: Into<anyhow::Error>>(call: &'static str) -> impl FnOnce(E) -> Self {
+        move |source| BotError::Rpc {
+            call,
+            source: source.into(),
+        }
+    }
+
+    /// Fixed pause before the next round, or `None` for failures of flaky RPC
+    /// nodes and price sources, which are retried with backoff instead
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            BotError::Rpc { .. }
+            | BotError::PriceUnavailable { .. }
+            | BotError::Approval(_)
+            | BotError::Submission(_) => None,
+            // Needs the market or the network to move first
+            BotError::PriceRejected(_)
+            | BotError::NoLiquidity
+            | BotError::Unrepresentable(_)
+            | BotError::GasPriceTooHigh(_)
+            | BotError::SimulationReverted(_)
+            | BotError::SwapFailed(_)
+            | BotError::VolumeCapReached { .. }
+            | BotError::Halted { .. } => Some(Duration::from_secs(9)),
+            BotError::ShuttingDown => Some(Duration::ZERO),
+            // Needs an operator to top up the wallet
+            BotError::InsufficientBalance { .. } | BotError::InsufficientGas { .. } => {
+                Some(Duration::from_secs(30))
+            }
+        }
+    }
+
+    /// Whether the pair should be reported Down on Kuma, with this error as message
+    pub fn reports_down(&self) -> bool {
+        matches!(
+            self,
+            BotError::PriceRejected(_)
+                | BotError::InsufficientBalance { .. }
+                | BotError::InsufficientGas { .. }
+                | BotError::GasPriceTooHigh(_)
+                | BotError::SwapFailed(_)
+                | BotError::VolumeCapReached { .. }
+                | BotError::Halted { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_per_variant() {
+        let rpc = BotError::rpc("fetch reserves")(anyhow::anyhow!("connection reset"));
+        assert_eq!(
+            rpc.to_string(),
+            "Failed to fetch reserves: connection reset"
+        );
+        assert_eq!(rpc.retry_after(), None);
+        assert!(!rpc.reports_down());
+
+        let balance = BotError::InsufficientBalance {
+            symbol: "WETH".to_string(),
+            wallet: Address::ZERO,
+        };
+        assert_eq!(
+            balance.to_string(),
+            format!(
+                "Insufficient WETH balance. Top up address {}",
+                Address::ZERO
+            )
+        );
+        assert_eq!(balance.retry_after(), Some(Duration::from_secs(30)));
+        assert!(balance.reports_down());
+
+        let reverted = BotError::SimulationReverted(RevertReason::InsufficientOutputAmount);
+        assert_eq!(reverted.retry_after(), Some(Duration::from_secs(9)));
+        assert!(!reverted.reports_down());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::config::{GasConfig, GasMode};
+use alloy::eips::BlockNumberOrTag;
+use alloy::network::TransactionBuilder;
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use anyhow::{Result, anyhow};
+use thiserror::Error;
+
+/// The network is too expensive to trade on right now
+#[derive(Debug, Error)]
+#[error("Network fee {fee} wei exceeds the {max} wei limit")]
+pub struct GasPriceTooHighError {
+    pub fee: u128,
+    pub max: u128,
+}
+
+/// Fees to set on a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasFees {
+    Legacy {
+        gas_price: u128,
+    },
+    Eip1559 {
+        max_fee_per_gas: u128,
+        max_priority_fee_per_gas: u128,
+    },
+}
+
+impl GasFees {
+    /// Fees already set on `tx`, if any
+    pub fn of(tx: &TransactionRequest) -> Option<Self> {
+        match (tx.gas_price, tx.max_fee_per_gas) {
+            (Some(gas_price), _) => Some(GasFees::Legacy { gas_price }),
+            (None, Some(max_fee_per_gas)) => Some(GasFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas: tx.max_priority_fee_per_gas.unwrap_or_default(),
+            }),
+            (None, None) => None,
+        }
+    }
+
+    /// Set these fees on `tx`, clearing those of the other kind
+    pub fn apply(self, mut tx: TransactionRequest) -> TransactionRequest {
+        match self {
+            GasFees::Legacy { gas_price } => {
+                tx.max_fee_per_gas = None;
+                tx.max_priority_fee_per_gas = None;
+                tx.with_gas_price(gas_price)
+            }
+            GasFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                tx.gas_price = None;
+                tx.with_max_fee_per_gas(max_fee_per_gas)
+                    .with_max_priority_fee_per_gas(max_priority_fee_per_gas)
+            }
+        }
+    }
+}
+
+/// Fees to pay along with the fee the network currently charges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasEstimate {
+    /// Next block base fee, or the gas price for legacy transactions
+    pub network_fee: u128,
+    pub fees: GasFees,
+}
+
+impl GasEstimate {
+    /// Price per gas the transaction is expected to actually pay
+    pub fn expected_gas_price(&self) -> u128 {
+        match self.fees {
+            GasFees::Legacy { gas_price } => gas_price,
+            GasFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => (self.network_fee + max_priority_fee_per_gas).min(max_fee_per_gas),
+        }
+    }
+}
+
+/// Prices transactions according to the `[gas]` config
+#[derive(Debug, Clone)]
+pub struct GasStrategy {
+    config: GasConfig,
+}
+
+impl GasStrategy {
+    pub fn new(config: GasConfig) -> Self {
+        Self { config }
+    }
+
+    /// Gas limit to set on every transaction, if overridden
+    pub fn gas_limit(&self) -> Option<u64> {
+        self.config.gas_limit
+    }
+
+    /// Current fees for a new transaction, capped at `max_fee_per_gas`
+    pub async fn estimate<P: Provider>(&self, provider: &P) -> Result<GasEstimate> {
+        let estimate = match self.config.mode {
+            GasMode::Legacy => {
+                let gas_price = provider.get_gas_price().await?;
+                GasEstimate {
+                    network_fee: gas_price,
+                    fees: GasFees::Legacy { gas_price },
+                }
+            }
+            GasMode::Eip1559 => {
+                let history = provider
+                    .get_fee_history(
+                        self.config.fee_history_blocks,
+                        BlockNumberOrTag::Latest,
+                        &[self.config.priority_fee_percentile],
+                    )
+                    .await?;
+                let base_fee = history
+                    .next_block_base_fee()
+                    .ok_or_else(|| anyhow!("Fee history has no base fee"))?;
+                let rewards: Vec<u128> = history
+                    .reward
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|block| block.first().copied())
+                    .collect();
+                let priority_fee = median(rewards);
+                GasEstimate {
+                    network_fee: base_fee,
+                    fees: GasFees::Eip1559 {
+                        // Leaves room for the base fee to double before inclusion
+                        max_fee_per_gas: base_fee * 2 + priority_fee,
+                        max_priority_fee_per_gas: priority_fee,
+                    },
+                }
+            }
+        };
+        Ok(GasEstimate {
+            fees: self.cap(estimate.fees, estimate.network_fee)?,
+            ..estimate
+        })
+    }
+
+    /// Fail if the network fee is above `max_network_fee`
+    pub fn ensure_affordable(&self, estimate: &GasEstimate) -> Result<(), GasPriceTooHighError> {
+        match self.config.max_network_fee {
+            Some(max) if estimate.network_fee > max => Err(GasPriceTooHighError {
+                fee: estimate.network_fee,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Limit fees to `max_fee_per_gas`, failing if that leaves them below
+    /// `network_fee` so the transaction could never be included
+    pub fn cap(&self, fees: GasFees, network_fee: u128) -> Result<GasFees, GasPriceTooHighError> {
+        let Some(cap) = self.config.max_fee_per_gas else {
+            return Ok(fees);
+        };
+        if cap < network_fee {
+            return Err(GasPriceTooHighError {
+                fee: network_fee,
+                max: cap,
+            });
+        }
+        Ok(match fees {
+            GasFees::Legacy { gas_price } => GasFees::Legacy {
+                gas_price: gas_price.min(cap),
+            },
+            GasFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => GasFees::Eip1559 {
+                max_fee_per_gas: max_fee_per_gas.min(cap),
+                max_priority_fee_per_gas: max_priority_fee_per_gas.min(cap),
+            },
+        })
+    }
+}
+
+fn median(mut values: Vec<u128>) -> u128 {
+    values.sort_unstable();
+    values.get(values.len() / 2).copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strategy(max_fee_per_gas: Option<u128>, max_network_fee: Option<u128>) -> GasStrategy {
+        GasStrategy::new(GasConfig {
+            max_fee_per_gas,
+            max_network_fee,
+            ..GasConfig::default()
+        })
+    }
+
+    #[test]
+    fn caps_fees() {
+        let fees = GasFees::Eip1559 {
+            max_fee_per_gas: 300,
+            max_priority_fee_per_gas: 20,
+        };
+        assert_eq!(strategy(None, None).cap(fees, 140).unwrap(), fees);
+        assert_eq!(
+            strategy(Some(100), None).cap(fees, 100).unwrap(),
+            GasFees::Eip1559 {
+                max_fee_per_gas: 100,
+                max_priority_fee_per_gas: 20,
+            }
+        );
+        assert_eq!(
+            strategy(Some(100), None)
+                .cap(GasFees::Legacy { gas_price: 150 }, 90)
+                .unwrap(),
+            GasFees::Legacy { gas_price: 100 }
+        );
+        // A cap below the base fee could never be included
+        let error = strategy(Some(100), None).cap(fees, 101).unwrap_err();
+        assert_eq!((error.fee, error.max), (101, 100));
+    }
+
+    #[test]
+    fn refuses_expensive_network() {
+        let estimate = GasEstimate {
+            network_fee: 200,
+            fees: GasFees::Legacy { gas_price: 200 },
+        };
+        assert!(strategy(None, None).ensure_affordable(&estimate).is_ok());
+        assert!(
+            strategy(None, Some(200))
+                .ensure_affordable(&estimate)
+                .is_ok()
+        );
+        assert!(
+            strategy(None, Some(199))
+                .ensure_affordable(&estimate)
+                .is_err()
+        );
+    }
+}
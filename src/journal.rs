@@ -0,0 +1,270 @@
+use crate::pricing::SwapDirection;
+use crate::tx::SwapOutcome;
+use alloy::primitives::{TxHash, U256};
+use rusqlite::{Connection, params};
+use rust_decimal::Decimal;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::error;
+
+/// Schema changes in order. The database's `user_version` is the number of
+/// migrations already applied, so new ones must only ever be appended.
+const MIGRATIONS: &[&str] = &["CREATE TABLE rounds (
+        id INTEGER PRIMARY KEY,
+        pair TEXT NOT NULL,
+        -- Unix time in milliseconds
+        started_at INTEGER NOT NULL,
+        decision TEXT NOT NULL,
+        -- Raw token amounts and prices are decimal strings, as they overflow
+        -- SQLite's integers and reals
+        reserve0 TEXT,
+        reserve1 TEXT,
+        pool_price TEXT,
+        reference_price TEXT,
+        deviation_bps TEXT,
+        direction TEXT,
+        amount_in TEXT,
+        expected_out TEXT,
+        min_out TEXT,
+        gas_units INTEGER,
+        expected_profit TEXT,
+        tx_hash TEXT,
+        block INTEGER,
+        actual_in TEXT,
+        actual_out TEXT,
+        gas_used INTEGER,
+        effective_gas_price TEXT,
+        error TEXT
+    );
+    CREATE INDEX rounds_pair_started_at ON rounds (pair, started_at);"];
+
+/// How a round of the pair loop ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    InRange,
+    Unprofitable,
+    /// The trade limits left nothing to sell
+    NothingToSell,
+    Swapped,
+    /// Dry runs only
+    Simulated,
+    Failed,
+}
+
+impl Decision {
+    fn as_str(self) -> &'static str {
+        match self {
+            Decision::InRange => "in_range",
+            Decision::Unprofitable => "unprofitable",
+            Decision::NothingToSell => "nothing_to_sell",
+            Decision::Swapped => "swapped",
+            Decision::Simulated => "simulated",
+            Decision::Failed => "failed",
+        }
+    }
+}
+
+/// Everything a pair worker saw and did in one round. Fields stay `None`
+/// when the round ended before getting to them.
+#[derive(Debug, Clone)]
+pub struct RoundRecord {
+    pub pair: String,
+    pub started_at: SystemTime,
+    pub decision: Decision,
+    pub reserves: Option<(U256, U256)>,
+    pub pool_price: Option<Decimal>,
+    pub reference_price: Option<Decimal>,
+    pub deviation_bps: Option<Decimal>,
+    pub direction: Option<SwapDirection>,
+    pub amount_in: Option<U256>,
+    pub expected_out: Option<U256>,
+    pub min_out: Option<U256>,
+    pub gas_units: Option<u64>,
+    /// In input token units, after gas
+    pub expected_profit: Option<Decimal>,
+    /// Hash of the last transaction sent for the swap
+    pub tx_hash: Option<TxHash>,
+    pub outcome: Option<SwapOutcome>,
+    pub error: Option<String>,
+}
+
+impl RoundRecord {
+    pub fn new(pair: &str) -> Self {
+        Self {
+            pair: pair.to_string(),
+            started_at: SystemTime::now(),
+            decision: Decision::Failed,
+            reserves: None,
+            pool_price: None,
+            reference_price: None,
+            deviation_bps: None,
+            direction: None,
+            amount_in: None,
+            expected_out: None,
+            min_out: None,
+            gas_units: None,
+            expected_profit: None,
+            tx_hash: None,
+            outcome: None,
+            error: None,
+        }
+    }
+}
+
+/// Append-only SQLite log of every round, for auditing pegging quality and
+/// costs. Writes happen on a blocking thread so a slow disk never holds up
+/// trading, and failed writes are only logged.
+#[derive(Clone)]
+pub struct Journal {
+    sender: mpsc::UnboundedSender<RoundRecord>,
+}
+
+impl Journal {
+    /// Open the database at `path`, creating and migrating it as needed
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let mut connection = Connection::open(path)?;
+        migrate(&mut connection)?;
+
+        let (sender, mut receiver) = mpsc::unbounded_channel::<RoundRecord>();
+        tokio::task::spawn_blocking(move || {
+            while let Some(record) = receiver.blocking_recv() {
+                if let Err(e) = insert(&connection, &record) {
+                    error!("{}: failed to write round to journal: {}", record.pair, e);
+                }
+            }
+        });
+        Ok(Self { sender })
+    }
+
+    pub fn record(&self, record: RoundRecord) {
+        if self.sender.send(record).is_err() {
+            error!("Journal writer stopped, dropping round");
+        }
+    }
+}
+
+/// Apply the migrations the database hasn't seen yet
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version: u32 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (applied, migration) in (0..).zip(MIGRATIONS).skip(version as usize) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", applied + 1u32)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+fn insert(connection: &Connection, record: &RoundRecord) -> rusqlite::Result<usize> {
+    let started_at = record
+        .started_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    let direction = record.direction.map(|direction| match direction {
+        SwapDirection::Token0ToToken1 => "token0_to_token1",
+        SwapDirection::Token1ToToken0 => "token1_to_token0",
+    });
+    let outcome = record.outcome.as_ref();
+    connection.execute(
+        "INSERT INTO rounds (
+            pair, started_at, decision, reserve0, reserve1, pool_price,
+            reference_price, deviation_bps, direction, amount_in, expected_out,
+            min_out, gas_units, expected_profit, tx_hash, block, actual_in,
+            actual_out, gas_used, effective_gas_price, error
+        ) VALUES (
+            ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15,
+            ?16, ?17, ?18, ?19, ?20, ?21
+        )",
+        params![
+            record.pair,
+            started_at,
+            record.decision.as_str(),
+            text(record.reserves.map(|(reserve0, _)| reserve0)),
+            text(record.reserves.map(|(_, reserve1)| reserve1)),
+            text(record.pool_price),
+            text(record.reference_price),
+            text(record.deviation_bps),
+            direction,
+            text(record.amount_in),
+            text(record.expected_out),
+            text(record.min_out),
+            record.gas_units.map(|gas_units| gas_units as i64),
+            text(record.expected_profit),
+            text(record.tx_hash),
+            outcome.map(|outcome| outcome.block as i64),
+            text(outcome.map(|outcome| outcome.amount_in)),
+            text(outcome.map(|outcome| outcome.amount_out)),
+            outcome.map(|outcome| outcome.gas_used as i64),
+            text(outcome.map(|outcome| outcome.effective_gas_price)),
+            record.error,
+        ],
+    )
+}
+
+fn text<T: ToString>(value: Option<T>) -> Option<String> {
+    value.map(|value| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn migrates_once_and_stores_rounds() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        migrate(&mut connection).unwrap();
+        let version: u32 = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+
+        let mut record = RoundRecord::new("WETH-USDT");
+        record.decision = Decision::Swapped;
+        record.reserves = Some((U256::MAX, U256::from(7)));
+        record.deviation_bps = Some(dec!(61.5));
+        record.direction = Some(SwapDirection::Token1ToToken0);
+        record.outcome = Some(SwapOutcome {
+            tx_hash: TxHash::ZERO,
+            block: 42,
+            amount_in: U256::from(100),
+            amount_out: U256::from(99),
+            gas_used: 21000,
+            effective_gas_price: 1_000_000_000,
+        });
+        insert(&connection, &record).unwrap();
+
+        let row: (String, String, String, String, i64, String, Option<String>) = connection
+            .query_row(
+                "SELECT decision, reserve0, deviation_bps, direction, block, actual_out, error FROM rounds",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            row,
+            (
+                "swapped".to_string(),
+                U256::MAX.to_string(),
+                "61.5".to_string(),
+                "token1_to_token0".to_string(),
+                42,
+                "99".to_string(),
+                None
+            )
+        );
+    }
+}
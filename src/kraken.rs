@@ -1,12 +1,107 @@
+use crate::config::KrakenPriceMode;
+use crate::kraken_ws::{self, KrakenPriceCache};
+use crate::price_source::{PriceQuote, PriceSource};
 use anyhow::{Result, anyhow};
+use async_trait::async_trait;
 use reqwest::Client;
 use rust_decimal::{Decimal, dec};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Deserialize)]
+struct TickerResponse {
+    error: Vec<String>,
+    result: Option<HashMap<String, RawTicker>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TradesResponse {
+    error: Vec<String>,
+    /// Trades keyed by pair, plus a "last" cursor
+    result: Option<HashMap<String, serde_json::Value>>,
+}
+
+/// Time of the most recent trade in a Trades result. Each trade is an array
+/// of price, volume, time in seconds, and more.
+fn last_trade_time(result: HashMap<String, serde_json::Value>) -> Option<SystemTime> {
+    let (_, trades) = result.into_iter().find(|(key, _)| key != "last")?;
+    let time = trades.as_array()?.last()?.get(2)?.as_f64()?;
+    UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(time).ok()?)
+}
+
+/// Ticker entry as returned by Kraken: every field is an array of strings
+#[derive(Debug, Deserialize)]
+struct RawTicker {
+    a: (Decimal, Decimal, Decimal),
+    b: (Decimal, Decimal, Decimal),
+    c: (Decimal, Decimal),
+    v: (Decimal, Decimal),
+    p: (Decimal, Decimal),
+    t: (u64, u64),
+    l: (Decimal, Decimal),
+    h: (Decimal, Decimal),
+    o: Decimal,
+}
+
+/// Parsed Kraken ticker for a single pair
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KrakenTicker {
+    /// Best ask price
+    pub ask: Decimal,
+    /// Best bid price
+    pub bid: Decimal,
+    /// Last trade price
+    pub last: Decimal,
+    /// Volume traded over the last 24 hours
+    pub volume_24h: Decimal,
+    /// Volume weighted average price over the last 24 hours
+    pub vwap_24h: Decimal,
+    /// Number of trades over the last 24 hours
+    pub trades_24h: u64,
+    /// Lowest price over the last 24 hours
+    pub low_24h: Decimal,
+    /// Highest price over the last 24 hours
+    pub high_24h: Decimal,
+    /// Today's opening price
+    pub open: Decimal,
+}
+
+impl From<RawTicker> for KrakenTicker {
+    fn from(raw: RawTicker) -> Self {
+        Self {
+            ask: raw.a.0,
+            bid: raw.b.0,
+            last: raw.c.0,
+            volume_24h: raw.v.1,
+            vwap_24h: raw.p.1,
+            trades_24h: raw.t.1,
+            low_24h: raw.l.1,
+            high_24h: raw.h.1,
+            open: raw.o,
+        }
+    }
+}
+
+impl KrakenTicker {
+    /// Midpoint between best bid and best ask
+    pub fn mid(&self) -> Decimal {
+        (self.bid + self.ask) / dec!(2)
+    }
+}
 
 #[derive(Clone)]
 pub struct KrakenClient {
     client: Client,
 }
 
+impl Default for KrakenClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KrakenClient {
     pub fn new() -> Self {
         Self {
@@ -14,34 +109,49 @@ impl KrakenClient {
         }
     }
 
-    pub async fn get_price(&self, pair: &str, reverse: bool) -> Result<Decimal> {
+    /// Fetch the full ticker for a pair, e.g. "ETHUSD"
+    pub async fn get_ticker(&self, pair: &str) -> Result<KrakenTicker> {
         // Kraken public ticker endpoint
         let url = format!("https://api.kraken.com/0/public/Ticker?pair={}", pair);
         let resp = self.client.get(&url).send().await?;
         if !resp.status().is_success() {
             return Err(anyhow!("Kraken HTTP error: {}", resp.status()));
         }
-        let json: serde_json::Value = resp.json().await?;
-        if let Some(errors) = json.get("error").and_then(|e| e.as_array()) {
-            if !errors.is_empty() {
-                return Err(anyhow!("Kraken API error: {:?}", errors));
-            }
-        }
-        let result = json
-            .get("result")
-            .ok_or_else(|| anyhow!("Missing result in Kraken response"))?;
-        // The key inside result can be different from requested pair, so pick the first object value.
-        let first_pair_obj = result
-            .as_object()
-            .and_then(|m| m.values().next())
-            .ok_or_else(|| anyhow!("Missing pair data in Kraken result"))?;
-        let last_trade = first_pair_obj
-            .get("c")
-            .and_then(|c| c.as_array())
-            .and_then(|arr| arr.get(0))
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| anyhow!("Missing last trade price in Kraken result"))?;
-        let price = last_trade.parse()?;
+        let response: TickerResponse = resp.json().await?;
+        if !response.error.is_empty() {
+            return Err(anyhow!("Kraken API error: {:?}", response.error));
+        }
+        // The key inside result can be different from requested pair, so pick the first value.
+        response
+            .result
+            .and_then(|result| result.into_values().next())
+            .map(KrakenTicker::from)
+            .ok_or_else(|| anyhow!("Missing pair data in Kraken result"))
+    }
+
+    /// Time of the latest trade of a pair, which the ticker doesn't carry
+    pub async fn get_last_trade_time(&self, pair: &str) -> Result<SystemTime> {
+        let url = format!(
+            "https://api.kraken.com/0/public/Trades?pair={}&count=1",
+            pair
+        );
+        let resp = self.client.get(&url).send().await?;
+        if !resp.status().is_success() {
+            return Err(anyhow!("Kraken HTTP error: {}", resp.status()));
+        }
+        let response: TradesResponse = resp.json().await?;
+        if !response.error.is_empty() {
+            return Err(anyhow!("Kraken API error: {:?}", response.error));
+        }
+        response
+            .result
+            .and_then(last_trade_time)
+            .ok_or_else(|| anyhow!("Missing trade data in Kraken result"))
+    }
+
+    /// Last trade price for a pair, optionally inverted (1/price)
+    pub async fn get_price(&self, pair: &str, reverse: bool) -> Result<Decimal> {
+        let price = self.get_ticker(pair).await?.last;
         if reverse {
             Ok(dec!(1.0) / price)
         } else {
@@ -49,3 +159,144 @@ impl KrakenClient {
         }
     }
 }
+
+/// Kraken ticker read with a configurable price mode, either polled over
+/// REST or taken from the shared WebSocket cache
+pub struct KrakenSource {
+    client: KrakenClient,
+    mode: KrakenPriceMode,
+    rolling_window: usize,
+    samples: Mutex<HashMap<String, VecDeque<Decimal>>>,
+    cache: Option<KrakenPriceCache>,
+    ws_symbol: Option<String>,
+}
+
+impl KrakenSource {
+    pub fn new(client: KrakenClient, mode: KrakenPriceMode, rolling_window: usize) -> Self {
+        Self {
+            client,
+            mode,
+            rolling_window: rolling_window.max(1),
+            samples: Mutex::new(HashMap::new()),
+            cache: None,
+            ws_symbol: None,
+        }
+    }
+
+    /// Read prices from the WebSocket cache instead of polling REST.
+    /// `ws_symbol` overrides the symbol derived from the REST pair name.
+    pub fn with_cache(mut self, cache: KrakenPriceCache, ws_symbol: Option<String>) -> Self {
+        self.cache = Some(cache);
+        self.ws_symbol = ws_symbol;
+        self
+    }
+
+    fn select(&self, pair: &str, last: Decimal, mid: Decimal, vwap_24h: Decimal) -> Decimal {
+        match self.mode {
+            KrakenPriceMode::Last => last,
+            KrakenPriceMode::Mid => mid,
+            KrakenPriceMode::Vwap => vwap_24h,
+            KrakenPriceMode::Rolling => self.rolling_average(pair, last),
+        }
+    }
+
+    /// Record the last trade price and return the average of the retained samples
+    fn rolling_average(&self, pair: &str, last: Decimal) -> Decimal {
+        let mut samples = self.samples.lock().expect("samples lock poisoned");
+        let window = samples.entry(pair.to_string()).or_default();
+        window.push_back(last);
+        while window.len() > self.rolling_window {
+            window.pop_front();
+        }
+        window.iter().sum::<Decimal>() / Decimal::from(window.len())
+    }
+}
+
+#[async_trait]
+impl PriceSource for KrakenSource {
+    fn name(&self) -> &str {
+        "kraken"
+    }
+
+    async fn price(&self, pair: &str) -> Result<PriceQuote> {
+        if let Some(cache) = &self.cache {
+            let symbol = self
+                .ws_symbol
+                .clone()
+                .or_else(|| kraken_ws::ws_symbol(pair))
+                .ok_or_else(|| anyhow!("No Kraken WebSocket symbol for {}", pair))?;
+            let ticker = cache.get(&symbol)?;
+            return Ok(PriceQuote {
+                price: self.select(pair, ticker.last, ticker.mid(), ticker.vwap_24h),
+                timestamp: ticker.received_at,
+            });
+        }
+
+        // The ticker has no timestamp, so the quote is as old as the last
+        // trade and a Kraken serving stale data is caught by the age check
+        let (ticker, traded_at) = futures::try_join!(
+            self.client.get_ticker(pair),
+            self.client.get_last_trade_time(pair)
+        )?;
+        Ok(PriceQuote {
+            price: self.select(pair, ticker.last, ticker.mid(), ticker.vwap_24h),
+            timestamp: traded_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICKER_RESPONSE: &str = r#"{"error":[],"result":{"XETHZUSD":{
+        "a":["2501.10000","12","12.000"],
+        "b":["2500.90000","3","3.000"],
+        "c":["2501.00000","0.05000000"],
+        "v":["1520.35829418","4012.70925611"],
+        "p":["2498.51022","2490.33470"],
+        "t":[4921,12870],
+        "l":["2470.00000","2455.12000"],
+        "h":["2515.00000","2520.00000"],
+        "o":"2480.00000"}}}"#;
+
+    #[test]
+    fn parses_ticker_response() {
+        let response: TickerResponse = serde_json::from_str(TICKER_RESPONSE).unwrap();
+        let ticker: KrakenTicker = response
+            .result
+            .unwrap()
+            .into_values()
+            .next()
+            .unwrap()
+            .into();
+        assert_eq!(ticker.last, dec!(2501));
+        assert_eq!(ticker.mid(), dec!(2501));
+        assert_eq!(ticker.vwap_24h, dec!(2490.3347));
+        assert_eq!(ticker.trades_24h, 12870);
+    }
+
+    #[test]
+    fn parses_last_trade_time() {
+        let response: TradesResponse = serde_json::from_str(
+            r#"{"error":[],"result":{"XETHZUSD":[
+            ["2500.90000","0.10000000",1715772041.2500,"s","l","",70151040],
+            ["2501.00000","0.05000000",1715772043.5000,"b","m","",70151041]],
+            "last":"1715772043500000000"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            last_trade_time(response.result.unwrap()),
+            Some(UNIX_EPOCH + Duration::from_millis(1_715_772_043_500))
+        );
+    }
+
+    #[test]
+    fn rolling_average_keeps_window() {
+        let source = KrakenSource::new(KrakenClient::new(), KrakenPriceMode::Rolling, 2);
+        assert_eq!(source.rolling_average("ETHUSD", dec!(100)), dec!(100));
+        assert_eq!(source.rolling_average("ETHUSD", dec!(110)), dec!(105));
+        assert_eq!(source.rolling_average("ETHUSD", dec!(130)), dec!(120));
+        assert_eq!(source.rolling_average("XBTUSD", dec!(50000)), dec!(50000));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::config::RetryConfig;
+use crate::price_source::StalePriceError;
+use crate::retry::Backoff;
+use anyhow::{Result, anyhow};
+use futures::{SinkExt, StreamExt};
+use rust_decimal::{Decimal, dec};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::time::{sleep, timeout};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, warn};
+
+/// Kraken WebSocket v2 public endpoint
+pub const KRAKEN_WS_URL: &str = "wss://ws.kraken.com/v2";
+
+/// Reconnect if nothing (not even a heartbeat) arrives for this long
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Quote currencies recognised when deriving a WebSocket symbol from a REST pair
+const QUOTE_CURRENCIES: &[&str] = &[
+    "USDT", "USDC", "USD", "EUR", "GBP", "CAD", "JPY", "CHF", "AUD", "DAI", "XBT", "BTC", "ETH",
+];
+
+/// Converts a REST pair name ("ETHUSD", "XBTUSDT", "XETHZUSD") into a WebSocket
+/// v2 symbol ("ETH/USD", "BTC/USDT", "ETH/USD"). Symbols that already contain a
+/// slash are kept. Returns `None` if the quote currency isn't recognised.
+pub fn ws_symbol(pair: &str) -> Option<String> {
+    fn normalize(asset: &str) -> &str {
+        match asset {
+            "XBT" => "BTC",
+            "XDG" => "DOGE",
+            other => other,
+        }
+    }
+
+    if pair.contains('/') {
+        return Some(pair.to_string());
+    }
+    let pair = pair.to_uppercase();
+    // Legacy names prefix crypto assets with X and fiat with Z, e.g. XXBTZUSD
+    if pair.is_ascii()
+        && pair.len() == 8
+        && pair.starts_with('X')
+        && matches!(pair.as_bytes()[4], b'X' | b'Z')
+    {
+        return Some(format!(
+            "{}/{}",
+            normalize(&pair[1..4]),
+            normalize(&pair[5..])
+        ));
+    }
+    QUOTE_CURRENCIES.iter().find_map(|quote| {
+        pair.strip_suffix(quote)
+            .filter(|base| !base.is_empty())
+            .map(|base| format!("{}/{}", normalize(base), normalize(quote)))
+    })
+}
+
+/// Latest ticker values received for a symbol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CachedTicker {
+    pub bid: Decimal,
+    pub ask: Decimal,
+    pub last: Decimal,
+    pub vwap_24h: Decimal,
+    /// When the last update for this symbol arrived
+    pub received_at: SystemTime,
+}
+
+impl CachedTicker {
+    pub fn mid(&self) -> Decimal {
+        (self.bid + self.ask) / dec!(2)
+    }
+
+    /// Time since the last update, zero if the clock went backwards
+    pub fn age(&self) -> Duration {
+        self.received_at.elapsed().unwrap_or_default()
+    }
+}
+
+/// Ticker cache shared between the WebSocket feed and every pair worker
+#[derive(Clone)]
+pub struct KrakenPriceCache {
+    tickers: Arc<RwLock<HashMap<String, CachedTicker>>>,
+    /// Symbols Kraken refused to subscribe to, with its reason
+    rejected: Arc<RwLock<HashMap<String, String>>>,
+    stale_after: Duration,
+}
+
+impl KrakenPriceCache {
+    pub fn new(stale_after: Duration) -> Self {
+        Self {
+            tickers: Arc::new(RwLock::new(HashMap::new())),
+            rejected: Arc::new(RwLock::new(HashMap::new())),
+            stale_after,
+        }
+    }
+
+    /// Latest ticker for a WebSocket symbol, failing if missing or stale
+    pub fn get(&self, symbol: &str) -> Result<CachedTicker> {
+        if let Some(reason) = self
+            .rejected
+            .read()
+            .expect("ticker cache lock poisoned")
+            .get(symbol)
+        {
+            return Err(anyhow!(
+                "Kraken WebSocket rejected the {} subscription: {}",
+                symbol,
+                reason
+            ));
+        }
+        let ticker = self
+            .tickers
+            .read()
+            .expect("ticker cache lock poisoned")
+            .get(symbol)
+            .copied()
+            .ok_or_else(|| anyhow!("No Kraken WebSocket ticker received yet for {}", symbol))?;
+        let age = ticker.age();
+        if age > self.stale_after {
+            return Err(StalePriceError {
+                age,
+                max_age: self.stale_after,
+            }
+            .into());
+        }
+        Ok(ticker)
+    }
+
+    fn insert(&self, symbol: String, ticker: CachedTicker) {
+        self.tickers
+            .write()
+            .expect("ticker cache lock poisoned")
+            .insert(symbol, ticker);
+    }
+
+    fn reject(&self, symbol: String, reason: String) {
+        self.rejected
+            .write()
+            .expect("ticker cache lock poisoned")
+            .insert(symbol, reason);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChannelMessage {
+    channel: Option<String>,
+    #[serde(default)]
+    data: Vec<serde_json::Value>,
+    /// Set on responses to requests such as `subscribe`
+    method: Option<String>,
+    success: Option<bool>,
+    error: Option<String>,
+    symbol: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TickerData {
+    symbol: String,
+    bid: Decimal,
+    ask: Decimal,
+    last: Decimal,
+    vwap: Decimal,
+}
+
+/// Subscribes to the Kraken WebSocket v2 ticker channel and keeps a
+/// [`KrakenPriceCache`] up to date, reconnecting whenever the stream drops
+pub struct KrakenTickerFeed {
+    url: String,
+    symbols: Vec<String>,
+    cache: KrakenPriceCache,
+    retry: RetryConfig,
+}
+
+impl KrakenTickerFeed {
+    pub fn new(
+        url: String,
+        symbols: Vec<String>,
+        cache: KrakenPriceCache,
+        retry: RetryConfig,
+    ) -> Self {
+        Self {
+            url,
+            symbols,
+            cache,
+            retry,
+        }
+    }
+
+    /// Run the feed forever
+    pub async fn run(self) {
+        let mut backoff = Backoff::new(self.retry.clone());
+        loop {
+            let connected = Instant::now();
+            let result = self.stream().await;
+            let delay = backoff.stopped(connected.elapsed()).delay();
+            match result {
+                Ok(()) => warn!(
+                    "Kraken WebSocket closed, reconnecting in {}ms",
+                    delay.as_millis()
+                ),
+                Err(e) => error!(
+                    "Kraken WebSocket error: {}, reconnecting in {}ms",
+                    e,
+                    delay.as_millis()
+                ),
+            }
+            sleep(delay).await;
+        }
+    }
+
+    /// Connect, subscribe and process messages until the connection ends
+    async fn stream(&self) -> Result<()> {
+        let (mut ws, _) = connect_async(&self.url).await?;
+        let subscribe = serde_json::json!({
+            "method": "subscribe",
+            "params": {
+                "channel": "ticker",
+                "symbol": self.symbols,
+                // Update on every best bid/offer change rather than only on
+                // trades, so quiet markets don't look stale
+                "event_trigger": "bbo",
+            }
+        });
+        ws.send(Message::text(subscribe.to_string())).await?;
+        info!(
+            "Subscribed to Kraken WebSocket ticker for {}",
+            self.symbols.join(", ")
+        );
+
+        loop {
+            let message = match timeout(READ_TIMEOUT, ws.next()).await {
+                Ok(Some(message)) => message?,
+                Ok(None) => return Ok(()),
+                Err(_) => return Err(anyhow!("no message for {}s", READ_TIMEOUT.as_secs())),
+            };
+            match message {
+                Message::Text(text) => self.handle(&text),
+                Message::Ping(payload) => ws.send(Message::Pong(payload)).await?,
+                Message::Close(_) => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    fn handle(&self, text: &str) {
+        let message: ChannelMessage = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Ignoring Kraken WebSocket message {}: {}", text, e);
+                return;
+            }
+        };
+        if message.method.as_deref() == Some("subscribe") {
+            if message.success == Some(false) {
+                let symbol = message.symbol.unwrap_or_default();
+                let reason = message
+                    .error
+                    .unwrap_or_else(|| "no reason given".to_string());
+                error!(
+                    "Kraken WebSocket rejected ticker subscription for {}: {}",
+                    symbol, reason
+                );
+                self.cache.reject(symbol, reason);
+            } else {
+                debug!("Kraken WebSocket subscription acknowledged: {}", text);
+            }
+            return;
+        }
+        if message.channel.as_deref() != Some("ticker") {
+            return;
+        }
+        let received_at = SystemTime::now();
+        for entry in message.data {
+            match serde_json::from_value::<TickerData>(entry) {
+                Ok(data) => self.cache.insert(
+                    data.symbol,
+                    CachedTicker {
+                        bid: data.bid,
+                        ask: data.ask,
+                        last: data.last,
+                        vwap_24h: data.vwap,
+                        received_at,
+                    },
+                ),
+                Err(e) => warn!("Malformed Kraken ticker update: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_ws_symbols() {
+        assert_eq!(ws_symbol("ETHUSD").as_deref(), Some("ETH/USD"));
+        assert_eq!(ws_symbol("XBTUSDT").as_deref(), Some("BTC/USDT"));
+        assert_eq!(ws_symbol("ETHXBT").as_deref(), Some("ETH/BTC"));
+        assert_eq!(ws_symbol("XDGEUR").as_deref(), Some("DOGE/EUR"));
+        assert_eq!(ws_symbol("XXBTZUSD").as_deref(), Some("BTC/USD"));
+        assert_eq!(ws_symbol("XETHZEUR").as_deref(), Some("ETH/EUR"));
+        assert_eq!(ws_symbol("SOL/USD").as_deref(), Some("SOL/USD"));
+        assert_eq!(ws_symbol("ETHSOL"), None);
+    }
+
+    #[test]
+    fn ticker_update_fills_cache() {
+        let cache = KrakenPriceCache::new(Duration::from_secs(60));
+        let feed = KrakenTickerFeed::new(
+            KRAKEN_WS_URL.to_string(),
+            vec!["ETH/USD".to_string()],
+            cache.clone(),
+            RetryConfig::default(),
+        );
+        feed.handle(
+            r#"{"channel":"ticker","type":"update","data":[{"symbol":"ETH/USD",
+            "bid":2500.9,"bid_qty":3.0,"ask":2501.1,"ask_qty":12.0,"last":2501.0,
+            "volume":4012.7,"vwap":2490.33,"low":2455.12,"high":2520.0,
+            "change":21.0,"change_pct":0.85}]}"#,
+        );
+        let ticker = cache.get("ETH/USD").unwrap();
+        assert_eq!(ticker.mid(), dec!(2501));
+        assert_eq!(ticker.vwap_24h, dec!(2490.33));
+        assert!(cache.get("BTC/USD").is_err());
+    }
+
+    #[test]
+    fn rejected_subscription_is_reported() {
+        let cache = KrakenPriceCache::new(Duration::from_secs(60));
+        let feed = KrakenTickerFeed::new(
+            KRAKEN_WS_URL.to_string(),
+            vec!["ETH/USD".to_string(), "ETH/USDX".to_string()],
+            cache.clone(),
+            RetryConfig::default(),
+        );
+        feed.handle(
+            r#"{"method":"subscribe","result":{"channel":"ticker","symbol":"ETH/USD"},
+            "success":true,"time_in":"2024-05-15T11:20:43.013486Z",
+            "time_out":"2024-05-15T11:20:43.013564Z"}"#,
+        );
+        feed.handle(
+            r#"{"error":"Currency pair not supported ETH/USDX","method":"subscribe",
+            "success":false,"symbol":"ETH/USDX","time_in":"2024-05-15T11:20:43.013486Z",
+            "time_out":"2024-05-15T11:20:43.013564Z"}"#,
+        );
+        let error = cache.get("ETH/USDX").unwrap_err().to_string();
+        assert!(error.contains("Currency pair not supported"), "{error}");
+        assert!(
+            cache
+                .get("ETH/USD")
+                .unwrap_err()
+                .to_string()
+                .contains("No Kraken WebSocket ticker received yet")
+        );
+    }
+}
@@ -26,7 +26,8 @@ impl KumaStatus {
 #[derive(Clone)]
 pub struct KumaPushClient {
     client: Client,
-    base_url: reqwest::Url,
+    /// `None` when pushes are disabled
+    base_url: Option<reqwest::Url>,
 }
 
 impl KumaPushClient {
@@ -37,7 +38,19 @@ impl KumaPushClient {
             .build()
             .expect("Failed to create HTTP client");
 
-        Self { client, base_url }
+        Self {
+            client,
+            base_url: Some(base_url),
+        }
+    }
+
+    /// Create a client that only logs status updates, so a dry run doesn't
+    /// flip the monitors of a live bot
+    pub fn disabled() -> Self {
+        Self {
+            client: Client::new(),
+            base_url: None,
+        }
     }
 
     /// Send a status update to the Kuma push endpoint
@@ -61,8 +74,16 @@ impl KumaPushClient {
     /// }
     /// ```
     pub async fn push(&self, id: &str, status: KumaStatus, msg: Option<&str>) -> Result<()> {
+        let Some(base_url) = &self.base_url else {
+            debug!(
+                "Kuma push disabled, not sending {} for ID: {}",
+                status.as_str(),
+                id
+            );
+            return Ok(());
+        };
         // Build the URL with the ID and query parameters
-        let mut url = self.base_url.join(&format!("api/push/{}", id))?;
+        let mut url = base_url.join(&format!("api/push/{}", id))?;
 
         // Add status parameter
         url.query_pairs_mut().append_pair("status", status.as_str());
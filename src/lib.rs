@@ -1,10 +1,30 @@
+pub mod binance;
 pub mod bot;
+pub mod breaker;
+pub mod coinbase;
 pub mod config;
 pub mod contracts;
-pub mod kuma;
+pub mod error;
+pub mod gas;
+pub mod journal;
 pub mod kraken;
+pub mod kraken_ws;
+pub mod kuma;
+pub mod limits;
+pub mod oracle;
+pub mod paper;
+pub mod price_source;
+pub mod pricing;
+pub mod retry;
+pub mod stats;
+pub mod submitter;
+pub mod supervisor;
+pub mod trigger;
+pub mod tx;
+pub mod worker;
 
 pub use bot::TradingBot;
 pub use config::Config;
+pub use kraken::{KrakenClient, KrakenTicker};
 pub use kuma::{KumaPushClient, KumaStatus};
-pub use kraken::KrakenClient;
+pub use price_source::{PriceQuote, PriceSource};
@@ -0,0 +1,141 @@
+use alloy::primitives::U256;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Span of the rolling volume cap
+pub const VOLUME_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Caps on how much of one token a single swap may sell, in raw units
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TradeLimits {
+    pub max_trade_size: Option<U256>,
+    /// Share of the wallet balance, between 0 and 1
+    pub max_balance_fraction: Option<Decimal>,
+    /// Most that may be sold over any [`VOLUME_WINDOW`]
+    pub max_daily_volume: Option<U256>,
+}
+
+impl TradeLimits {
+    /// Largest input allowed right now, given the wallet balance and the
+    /// volume already sold within the window
+    pub fn max_input(&self, balance: U256, volume: U256) -> U256 {
+        let mut max = U256::MAX;
+        if let Some(size) = self.max_trade_size {
+            max = max.min(size);
+        }
+        if let Some(fraction) = self.max_balance_fraction {
+            max = max.min(fraction_of(balance, fraction));
+        }
+        if let Some(cap) = self.max_daily_volume {
+            max = max.min(cap.saturating_sub(volume));
+        }
+        max
+    }
+}
+
+/// `amount * fraction`, rounded down, for a fraction between 0 and 1
+fn fraction_of(amount: U256, fraction: Decimal) -> U256 {
+    let mantissa = U256::from(fraction.mantissa().unsigned_abs());
+    let scale = U256::from(10).pow(U256::from(fraction.scale()));
+    // Fractions are at most 1, so dividing first only loses the remainder
+    let (quotient, remainder) = amount.div_rem(scale);
+    quotient * mantissa + remainder * mantissa / scale
+}
+
+/// Swap inputs of one token over the last [`VOLUME_WINDOW`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VolumeWindow {
+    trades: VecDeque<(Instant, U256)>,
+}
+
+impl VolumeWindow {
+    pub fn record(&mut self, at: Instant, amount: U256) {
+        self.trades.push_back((at, amount));
+    }
+
+    /// Total sold within the window ending at `now`
+    pub fn volume(&mut self, now: Instant) -> U256 {
+        self.prune(now);
+        self.trades.iter().fold(U256::ZERO, |total, (_, amount)| {
+            total.saturating_add(*amount)
+        })
+    }
+
+    /// Time until the oldest trade leaves the window and frees up volume
+    pub fn frees_up_in(&mut self, now: Instant) -> Duration {
+        self.prune(now);
+        self.trades.front().map_or(Duration::ZERO, |(at, _)| {
+            VOLUME_WINDOW.saturating_sub(now.duration_since(*at))
+        })
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some((at, _)) = self.trades.front() {
+            if now.duration_since(*at) < VOLUME_WINDOW {
+                break;
+            }
+            self.trades.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn tightest_limit_wins() {
+        let limits = TradeLimits {
+            max_trade_size: Some(U256::from(500)),
+            max_balance_fraction: Some(dec!(0.25)),
+            max_daily_volume: Some(U256::from(1000)),
+        };
+        assert_eq!(
+            limits.max_input(U256::from(10_000), U256::ZERO),
+            U256::from(500)
+        );
+        assert_eq!(
+            limits.max_input(U256::from(1000), U256::ZERO),
+            U256::from(250)
+        );
+        assert_eq!(
+            limits.max_input(U256::from(10_000), U256::from(900)),
+            U256::from(100)
+        );
+        assert_eq!(
+            limits.max_input(U256::from(10_000), U256::from(1200)),
+            U256::ZERO
+        );
+        assert_eq!(
+            TradeLimits::default().max_input(U256::ZERO, U256::ZERO),
+            U256::MAX
+        );
+    }
+
+    #[test]
+    fn fraction_does_not_overflow() {
+        assert_eq!(fraction_of(U256::MAX, Decimal::ONE), U256::MAX);
+        assert_eq!(fraction_of(U256::from(7), dec!(0.5)), U256::from(3));
+    }
+
+    #[test]
+    fn volume_rolls_off_after_a_day() {
+        let start = Instant::now();
+        let mut window = VolumeWindow::default();
+        window.record(start, U256::from(300));
+        window.record(start + Duration::from_secs(3600), U256::from(200));
+
+        let later = start + Duration::from_secs(7200);
+        assert_eq!(window.volume(later), U256::from(500));
+        assert_eq!(
+            window.frees_up_in(later),
+            VOLUME_WINDOW - Duration::from_secs(7200)
+        );
+
+        let next_day = start + VOLUME_WINDOW;
+        assert_eq!(window.volume(next_day), U256::from(200));
+        assert_eq!(window.frees_up_in(next_day), Duration::from_secs(3600));
+    }
+}
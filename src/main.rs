@@ -4,8 +4,10 @@ use hyperion_dex_bot::{Config, KumaPushClient, KumaStatus, TradingBot};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::watch;
 use tokio::time::sleep;
-use tracing::{error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 use tracing_subscriber::FmtSubscriber;
 
 /// UniswapV2 trading bot
@@ -15,6 +17,11 @@ struct Args {
     /// Path to the configuration file
     #[clap(short, long, value_parser, default_value = "config.toml")]
     config: PathBuf,
+
+    /// Simulate and log trades against a virtual wallet without signing or
+    /// sending any transaction or pushing any status to Kuma
+    #[clap(long)]
+    dry_run: bool,
 }
 
 #[tokio::main]
@@ -42,15 +49,31 @@ async fn main() -> Result<()> {
     info!("Configuration loaded successfully");
     info!("RPC URL: {}", config.rpc_url);
     info!("Number of pairs: {}", config.pairs.len());
+    if args.dry_run {
+        info!("Dry run: no transaction will be signed or sent, and no status pushed to Kuma");
+    }
 
     let base_kuma_url = reqwest::Url::parse(&config.base_kuma_url)?;
-    let kuma_push_client = Arc::new(KumaPushClient::new(base_kuma_url));
+    let kuma_push_client = Arc::new(if args.dry_run {
+        KumaPushClient::disabled()
+    } else {
+        KumaPushClient::new(base_kuma_url)
+    });
 
-    tokio::spawn({
+    let shutdown = CancellationToken::new();
+    tokio::spawn(watch_signals(shutdown.clone()));
+    let (resume_sender, resume) = watch::channel(0);
+    #[cfg(unix)]
+    tokio::spawn(watch_resume(resume_sender));
+    #[cfg(not(unix))]
+    drop(resume_sender);
+
+    let heartbeat = tokio::spawn({
         let kuma_push_client = kuma_push_client.clone();
         let push_id = config.general_push_id.clone();
+        let shutdown = shutdown.clone();
         async move {
-            loop {
+            while !shutdown.is_cancelled() {
                 if let Err(e) = kuma_push_client
                     .push(&push_id, KumaStatus::Up, Some("Bot is running"))
                     .await
@@ -58,17 +81,84 @@ async fn main() -> Result<()> {
                     error!("Failed to send status update to Kuma push: {}", e);
                 }
 
-                sleep(Duration::from_secs(55)).await;
+                tokio::select! {
+                    _ = sleep(Duration::from_secs(55)) => {}
+                    _ = shutdown.cancelled() => {}
+                }
             }
         }
     });
 
     // Create and run the trading bot
     info!("Initializing trading bot");
-    let bot = TradingBot::new(config, kuma_push_client).await?;
+    let general_push_id = config.general_push_id.clone();
+    let bot = TradingBot::new(
+        config,
+        kuma_push_client.clone(),
+        shutdown,
+        args.dry_run,
+        resume,
+    )
+    .await?;
 
     info!("Running trading bot");
-    bot.run().await?;
+    let totals = bot.run().await?;
+    // Make sure the last Up heartbeat cannot land after the final status
+    heartbeat.await?;
+
+    info!("Trading bot stopped");
+    for (name, totals) in &totals {
+        info!("{}: {}", name, totals);
+    }
+    let swaps: u64 = totals.iter().map(|(_, totals)| totals.swaps).sum();
+    let failed: u64 = totals.iter().map(|(_, totals)| totals.failed_swaps).sum();
+    let msg = format!(
+        "Bot stopped after {} confirmed and {} failed swaps",
+        swaps, failed
+    );
+    if let Err(e) = kuma_push_client
+        .push(&general_push_id, KumaStatus::Down, Some(&msg))
+        .await
+    {
+        error!("Failed to send status update to Kuma push: {}", e);
+    }
 
     Ok(())
 }
+
+/// Cancel `shutdown` on the first SIGINT or SIGTERM so workers can finish
+/// what they already sent; a second signal exits immediately
+async fn watch_signals(shutdown: CancellationToken) {
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to install SIGTERM handler");
+
+    for attempt in 0.. {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+
+        if attempt == 0 {
+            warn!("Shutdown requested, waiting for in-flight transactions");
+            shutdown.cancel();
+        } else {
+            warn!("Shutdown requested again, exiting immediately");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Resume every pair halted by its circuit breaker on SIGUSR1
+#[cfg(unix)]
+async fn watch_resume(resume: watch::Sender<u64>) {
+    let mut user1 = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())
+        .expect("failed to install SIGUSR1 handler");
+    while user1.recv().await.is_some() {
+        info!("Resuming halted pairs");
+        resume.send_modify(|generation| *generation += 1);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::price_source::{PriceQuote, ReferencePrice};
+use futures::future::join_all;
+use rust_decimal::{Decimal, dec};
+use thiserror::Error;
+use tracing::warn;
+
+/// Reasons the aggregated oracle refuses to produce a price
+#[derive(Debug, Error)]
+pub enum OracleError {
+    #[error("Only {received} of {required} required price sources responded")]
+    NoQuorum { received: usize, required: usize },
+    #[error(
+        "Price sources disagree: only {agreeing} of {required} required quotes within {max_spread_bps} bps of median {median} ({quotes})"
+    )]
+    Disagreement {
+        agreeing: usize,
+        required: usize,
+        max_spread_bps: u32,
+        median: Decimal,
+        quotes: String,
+    },
+}
+
+/// Median of several reference prices with outlier rejection
+#[derive(Clone)]
+pub struct MedianOracle {
+    sources: Vec<ReferencePrice>,
+    max_spread_bps: u32,
+    quorum: usize,
+}
+
+impl MedianOracle {
+    pub fn new(sources: Vec<ReferencePrice>, max_spread_bps: u32, quorum: usize) -> Self {
+        Self {
+            sources,
+            max_spread_bps,
+            quorum,
+        }
+    }
+
+    /// Query every source concurrently and aggregate the quotes that arrive
+    pub async fn quote(&self) -> Result<PriceQuote, OracleError> {
+        let results = join_all(self.sources.iter().map(|source| source.quote())).await;
+        let quotes: Vec<PriceQuote> = results
+            .into_iter()
+            .zip(&self.sources)
+            .filter_map(|(result, source)| match result {
+                Ok(quote) => Some(quote),
+                Err(e) => {
+                    warn!("Price source {} failed: {}", source.describe(), e);
+                    None
+                }
+            })
+            .collect();
+        aggregate(&quotes, self.max_spread_bps, self.quorum)
+    }
+
+    pub fn describe(&self) -> String {
+        let sources: Vec<String> = self.sources.iter().map(|s| s.describe()).collect();
+        format!("median[{}]", sources.join(", "))
+    }
+}
+
+fn median(sorted: &[Decimal]) -> Decimal {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / dec!(2)
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Combine quotes into a single price: quotes further than `max_spread_bps`
+/// from the median are dropped, and at least `quorum` must remain. The result
+/// is the median of the remaining quotes, stamped with the oldest of them.
+/// Quotes that aren't positive count as failed sources.
+pub fn aggregate(
+    quotes: &[PriceQuote],
+    max_spread_bps: u32,
+    quorum: usize,
+) -> Result<PriceQuote, OracleError> {
+    let (quotes, invalid): (Vec<PriceQuote>, Vec<PriceQuote>) =
+        quotes.iter().partition(|q| q.price > Decimal::ZERO);
+    for quote in invalid {
+        warn!("Ignoring non-positive price quote {}", quote.price);
+    }
+    if quotes.is_empty() || quotes.len() < quorum {
+        return Err(OracleError::NoQuorum {
+            received: quotes.len(),
+            required: quorum,
+        });
+    }
+
+    let mut prices: Vec<Decimal> = quotes.iter().map(|q| q.price).collect();
+    prices.sort();
+    let median_price = median(&prices);
+
+    let max_spread = Decimal::from(max_spread_bps) / dec!(10000);
+    let agreeing: Vec<&PriceQuote> = quotes
+        .iter()
+        .filter(|q| ((q.price - median_price) / median_price).abs() <= max_spread)
+        .collect();
+
+    if agreeing.is_empty() || agreeing.len() < quorum {
+        return Err(OracleError::Disagreement {
+            agreeing: agreeing.len(),
+            required: quorum,
+            max_spread_bps,
+            median: median_price,
+            quotes: prices
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        });
+    }
+
+    let mut agreeing_prices: Vec<Decimal> = agreeing.iter().map(|q| q.price).collect();
+    agreeing_prices.sort();
+    let timestamp = agreeing
+        .iter()
+        .map(|q| q.timestamp)
+        .min()
+        .expect("agreeing quotes are not empty");
+
+    Ok(PriceQuote {
+        price: median(&agreeing_prices),
+        timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn quotes(prices: &[Decimal]) -> Vec<PriceQuote> {
+        prices.iter().map(|p| PriceQuote::now(*p)).collect()
+    }
+
+    #[test]
+    fn median_of_agreeing_sources() {
+        let quote = aggregate(&quotes(&[dec!(2500), dec!(2502), dec!(2498)]), 50, 2).unwrap();
+        assert_eq!(quote.price, dec!(2500));
+    }
+
+    #[test]
+    fn outlier_is_rejected() {
+        let quote = aggregate(&quotes(&[dec!(2500), dec!(2502), dec!(3000)]), 50, 2).unwrap();
+        assert_eq!(quote.price, dec!(2501));
+    }
+
+    #[test]
+    fn disagreement_without_quorum() {
+        let err = aggregate(&quotes(&[dec!(2500), dec!(2600), dec!(2700)]), 50, 2).unwrap_err();
+        assert!(matches!(err, OracleError::Disagreement { agreeing: 1, .. }));
+    }
+
+    #[test]
+    fn too_few_responses() {
+        let err = aggregate(&quotes(&[dec!(2500)]), 50, 2).unwrap_err();
+        assert!(matches!(
+            err,
+            OracleError::NoQuorum {
+                received: 1,
+                required: 2
+            }
+        ));
+    }
+
+    #[test]
+    fn non_positive_quotes_count_as_failed() {
+        let err = aggregate(&quotes(&[Decimal::ZERO]), 100, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            OracleError::NoQuorum {
+                received: 0,
+                required: 1
+            }
+        ));
+        let quote = aggregate(
+            &quotes(&[Decimal::ZERO, dec!(-1), Decimal::ZERO, dec!(2500)]),
+            100,
+            1,
+        )
+        .unwrap();
+        assert_eq!(quote.price, dec!(2500));
+    }
+
+    #[test]
+    fn oldest_agreeing_timestamp_is_kept() {
+        let old = SystemTime::now() - Duration::from_secs(30);
+        let mut quotes = quotes(&[dec!(2500), dec!(2501)]);
+        quotes[1].timestamp = old;
+        assert_eq!(aggregate(&quotes, 50, 2).unwrap().timestamp, old);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::contracts::IERC20;
+use crate::pricing::{self, SwapDirection};
+use alloy::primitives::{Address, B256, U256, keccak256};
+use alloy::providers::Provider;
+use alloy::rpc::types::state::{StateOverride, StateOverridesBuilder};
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Storage slots searched for a token's `balanceOf` and `allowance` mappings
+const PROBED_SLOTS: u64 = 20;
+/// Written to a candidate slot to recognise it in the token's getter
+const PROBE_VALUE: U256 = U256::from_limbs([0x5eed, 0, 0, 1]);
+
+/// Where a compiler puts `mapping(key => ...)` entries: Solidity hashes the
+/// key before the mapping's slot, Vyper after it
+#[derive(Debug, Clone, Copy)]
+enum Layout {
+    Solidity,
+    Vyper,
+}
+
+fn mapping_slot(key: B256, slot: B256, layout: Layout) -> B256 {
+    let (first, second) = match layout {
+        Layout::Solidity => (key, slot),
+        Layout::Vyper => (slot, key),
+    };
+    keccak256([first.as_slice(), second.as_slice()].concat())
+}
+
+/// Storage slots of the wallet's balance of a token and its allowance to
+/// the router
+#[derive(Debug, Clone, Copy)]
+struct TokenSlots {
+    balance: B256,
+    allowance: B256,
+}
+
+/// Find a token's balance and allowance slots by overriding each candidate
+/// and checking whether the getter returns the override
+async fn probe_slots<P: Provider + Clone>(
+    provider: P,
+    token: Address,
+    holder: Address,
+    spender: Address,
+) -> Result<Option<TokenSlots>> {
+    let contract = IERC20::new(token, provider);
+    let probe = |slot: B256| {
+        StateOverridesBuilder::default()
+            .with_state_diff(token, [(slot, B256::from(PROBE_VALUE))])
+            .build()
+    };
+    let candidates = (0..PROBED_SLOTS).flat_map(|index| {
+        [Layout::Solidity, Layout::Vyper].map(|layout| (B256::from(U256::from(index)), layout))
+    });
+
+    let mut balance = None;
+    for (index, layout) in candidates.clone() {
+        let slot = mapping_slot(holder.into_word(), index, layout);
+        if contract.balanceOf(holder).state(probe(slot)).call().await? == PROBE_VALUE {
+            balance = Some(slot);
+            break;
+        }
+    }
+    let mut allowance = None;
+    for (index, layout) in candidates {
+        let owner = mapping_slot(holder.into_word(), index, layout);
+        let slot = mapping_slot(spender.into_word(), owner, layout);
+        if contract
+            .allowance(holder, spender)
+            .state(probe(slot))
+            .call()
+            .await?
+            == PROBE_VALUE
+        {
+            allowance = Some(slot);
+            break;
+        }
+    }
+    Ok(balance
+        .zip(allowance)
+        .map(|(balance, allowance)| TokenSlots { balance, allowance }))
+}
+
+#[derive(Debug, Default)]
+struct Balances {
+    tokens: HashMap<Address, U256>,
+    native: Option<U256>,
+    /// Probed storage slots per token, `None` where none were found
+    slots: HashMap<Address, Option<TokenSlots>>,
+}
+
+/// Virtual wallet shared by every pair of a dry run. Each balance is read
+/// from the real wallet the first time a pair needs it and from then on only
+/// moves with simulated swaps, so pairs sharing a token draw on one balance.
+#[derive(Debug, Clone, Default)]
+pub struct PaperWallet {
+    balances: Arc<Mutex<Balances>>,
+}
+
+impl PaperWallet {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Balances> {
+        self.balances.lock().expect("paper wallet lock poisoned")
+    }
+
+    /// Balance of `token`, if it is tracked yet
+    pub fn balance(&self, token: Address) -> Option<U256> {
+        self.lock().tokens.get(&token).copied()
+    }
+
+    /// Balance of the gas coin, if it is tracked yet
+    pub fn native(&self) -> Option<U256> {
+        self.lock().native
+    }
+
+    /// Start tracking `token` at `balance`, keeping the existing balance if
+    /// another pair got there first. Returns the tracked balance.
+    pub fn seed(&self, token: Address, balance: U256) -> U256 {
+        *self.lock().tokens.entry(token).or_insert(balance)
+    }
+
+    /// Start tracking the gas coin at `balance`, unless it already is
+    pub fn seed_native(&self, balance: U256) -> U256 {
+        *self.lock().native.get_or_insert(balance)
+    }
+
+    /// State override giving `holder` its paper balances of `token` and of
+    /// the gas coin, and `spender` an unlimited allowance of `token`, so a
+    /// swap can be simulated as if the paper wallet were real. Returns `None`
+    /// if the token's storage layout couldn't be found.
+    ///
+    /// The slots are probed once per token, so `holder` and `spender` must
+    /// not change between calls.
+    pub async fn state_override<P: Provider + Clone>(
+        &self,
+        provider: P,
+        token: Address,
+        holder: Address,
+        spender: Address,
+    ) -> Result<Option<StateOverride>> {
+        let cached = self.lock().slots.get(&token).copied();
+        let slots = match cached {
+            Some(slots) => slots,
+            None => {
+                let slots = probe_slots(provider, token, holder, spender).await?;
+                if slots.is_none() {
+                    warn!(
+                        "Storage of token {} not found, its dry-run swaps are booked without simulation",
+                        token
+                    );
+                }
+                self.lock().slots.insert(token, slots);
+                slots
+            }
+        };
+        let Some(slots) = slots else {
+            return Ok(None);
+        };
+        let balances = self.lock();
+        let balance = balances.tokens.get(&token).copied().unwrap_or_default();
+        let mut state = StateOverridesBuilder::default().with_state_diff(
+            token,
+            [
+                (slots.balance, B256::from(balance)),
+                (slots.allowance, B256::from(U256::MAX)),
+            ],
+        );
+        if let Some(native) = balances.native {
+            state = state.with_balance(holder, native);
+        }
+        Ok(Some(state.build()))
+    }
+
+    /// Book a simulated swap and the gas it would have paid, in wei
+    pub fn apply(
+        &self,
+        input: Address,
+        amount_in: U256,
+        output: Address,
+        amount_out: U256,
+        gas_cost: U256,
+    ) {
+        let mut balances = self.lock();
+        let tokens = &mut balances.tokens;
+        let input_balance = tokens.entry(input).or_default();
+        *input_balance = input_balance.saturating_sub(amount_in);
+        let output_balance = tokens.entry(output).or_default();
+        *output_balance = output_balance.saturating_add(amount_out);
+        if let Some(native) = &mut balances.native {
+            *native = native.saturating_sub(gas_cost);
+        }
+    }
+}
+
+/// What one pair's simulated swaps moved, in raw units, so the pair's PnL
+/// doesn't depend on what other pairs did with a shared token
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PaperAccount {
+    sold0: U256,
+    bought0: U256,
+    sold1: U256,
+    bought1: U256,
+    gas: U256,
+}
+
+impl PaperAccount {
+    /// Book a simulated swap and the gas it would have paid, in wei
+    pub fn apply(
+        &mut self,
+        direction: SwapDirection,
+        amount_in: U256,
+        amount_out: U256,
+        gas_cost: U256,
+    ) {
+        let (sold, bought) = match direction {
+            SwapDirection::Token0ToToken1 => (&mut self.sold0, &mut self.bought1),
+            SwapDirection::Token1ToToken0 => (&mut self.sold1, &mut self.bought0),
+        };
+        *sold = sold.saturating_add(amount_in);
+        *bought = bought.saturating_add(amount_out);
+        self.gas = self.gas.saturating_add(gas_cost);
+    }
+
+    /// Value gained by the swaps, in token1 units, with token0 valued at
+    /// `reference_price` and the gas coin at `native_price0` token0.
+    ///
+    /// Returns `None` if the amounts are too large to value.
+    pub fn pnl(
+        &self,
+        decimals0: u8,
+        decimals1: u8,
+        native_decimals: u8,
+        reference_price: Decimal,
+        native_price0: Decimal,
+    ) -> Option<Decimal> {
+        let token0 = pricing::to_decimal(self.bought0, decimals0)?
+            .checked_sub(pricing::to_decimal(self.sold0, decimals0)?)?;
+        let token1 = pricing::to_decimal(self.bought1, decimals1)?
+            .checked_sub(pricing::to_decimal(self.sold1, decimals1)?)?;
+        let gas = pricing::to_decimal(self.gas, native_decimals)?.checked_mul(native_price0)?;
+        token0
+            .checked_sub(gas)?
+            .checked_mul(reference_price)?
+            .checked_add(token1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn books_swaps_and_values_them() {
+        let mut account = PaperAccount::default();
+        // Sell 2 token0 (6 decimals) for 1.1 token1 (18 decimals), paying
+        // 0.01 of the gas coin
+        account.apply(
+            SwapDirection::Token0ToToken1,
+            U256::from(2_000_000u64),
+            U256::from(1_100_000_000_000_000_000u128),
+            U256::from(10_000_000_000_000_000u128),
+        );
+
+        // At 0.5 token1 per token0 the swap gained 0.1 token1; gas of 0.01
+        // coin at 2 token0 each costs 0.02 token0, or 0.01 token1
+        let pnl = account.pnl(6, 18, 18, dec!(0.5), dec!(2)).unwrap();
+        assert_eq!(pnl, dec!(0.09));
+        // A gas coin priced at zero leaves only the swap's gain
+        let pnl = account.pnl(6, 18, 18, dec!(0.5), Decimal::ZERO).unwrap();
+        assert_eq!(pnl, dec!(0.1));
+    }
+
+    #[test]
+    fn mapping_slots_follow_compiler_layout() {
+        // keccak256 of 64 zero bytes
+        let zero = B256::from_slice(&alloy::hex!(
+            "ad3228b676f7d3cd4284a5443f17f1962b36e491b30a40b2405849e597ba5fb5"
+        ));
+        assert_eq!(mapping_slot(B256::ZERO, B256::ZERO, Layout::Solidity), zero);
+        let key = Address::repeat_byte(1).into_word();
+        let slot = B256::from(U256::from(3));
+        assert_eq!(
+            mapping_slot(key, slot, Layout::Solidity),
+            keccak256([key.as_slice(), slot.as_slice()].concat())
+        );
+        assert_eq!(
+            mapping_slot(key, slot, Layout::Vyper),
+            keccak256([slot.as_slice(), key.as_slice()].concat())
+        );
+    }
+
+    #[tokio::test]
+    async fn probes_balance_and_allowance_slots() {
+        use alloy::providers::ProviderBuilder;
+        use alloy::transports::mock::Asserter;
+
+        let asserter = Asserter::new();
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let (token, holder, spender) = (
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3),
+        );
+        let word = |value: U256| B256::from(value).to_string();
+        // balanceOf sees the probe in Solidity slot 1, allowance in Vyper slot 0
+        for value in [U256::ZERO, U256::ZERO, PROBE_VALUE] {
+            asserter.push_success(&word(value));
+        }
+        for value in [U256::ZERO, PROBE_VALUE] {
+            asserter.push_success(&word(value));
+        }
+        let slots = probe_slots(provider, token, holder, spender)
+            .await
+            .unwrap()
+            .unwrap();
+        let index = |index: u64| B256::from(U256::from(index));
+        assert_eq!(
+            slots.balance,
+            mapping_slot(holder.into_word(), index(1), Layout::Solidity)
+        );
+        let owner = mapping_slot(holder.into_word(), index(0), Layout::Vyper);
+        assert_eq!(
+            slots.allowance,
+            mapping_slot(spender.into_word(), owner, Layout::Vyper)
+        );
+    }
+
+    #[test]
+    fn pairs_share_wallet_balances() {
+        let weth = Address::repeat_byte(1);
+        let usdt = Address::repeat_byte(2);
+        let dai = Address::repeat_byte(3);
+        let wallet = PaperWallet::default();
+        assert_eq!(wallet.seed(weth, U256::from(10)), U256::from(10));
+        wallet.seed(usdt, U256::from(100));
+        wallet.seed_native(U256::from(50));
+
+        // WETH-USDT sells 4 WETH, then WETH-DAI sees what is left
+        wallet.apply(weth, U256::from(4), usdt, U256::from(40), U256::from(5));
+        assert_eq!(wallet.seed(weth, U256::from(10)), U256::from(6));
+        wallet.seed(dai, U256::from(0));
+        wallet.apply(weth, U256::from(6), dai, U256::from(60), U256::from(5));
+
+        assert_eq!(wallet.balance(weth), Some(U256::ZERO));
+        assert_eq!(wallet.balance(usdt), Some(U256::from(140)));
+        assert_eq!(wallet.balance(dai), Some(U256::from(60)));
+        assert_eq!(wallet.native(), Some(U256::from(40)));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::binance::BinanceClient;
+use crate::coinbase::CoinbaseClient;
+use crate::config::PriceSourceConfig;
+use crate::kraken::{KrakenClient, KrakenSource};
+use crate::kraken_ws::KrakenPriceCache;
+use crate::oracle::MedianOracle;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures::FutureExt;
+use futures::future::{BoxFuture, try_join_all};
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// The reference price is older than the configured maximum age
+#[derive(Debug, Error)]
+#[error("Reference price is stale: {}s old, maximum age is {}s", .age.as_secs(), .max_age.as_secs())]
+pub struct StalePriceError {
+    pub age: Duration,
+    pub max_age: Duration,
+}
+
+/// A reference price together with the time it was observed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceQuote {
+    pub price: Decimal,
+    pub timestamp: SystemTime,
+}
+
+impl PriceQuote {
+    /// Create a quote observed right now
+    pub fn now(price: Decimal) -> Self {
+        Self {
+            price,
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    /// Time since the quote was observed, zero if the clock went backwards
+    pub fn age(&self) -> Duration {
+        self.timestamp.elapsed().unwrap_or_default()
+    }
+
+    /// Fail with [`StalePriceError`] if the quote is older than `max_age`
+    pub fn check_age(self, max_age: Duration) -> Result<Self> {
+        let age = self.age();
+        if age > max_age {
+            return Err(StalePriceError { age, max_age }.into());
+        }
+        Ok(self)
+    }
+
+    /// The reciprocal quote (1/price), keeping the observation time
+    pub fn inverted(self) -> Result<Self> {
+        if self.price.is_zero() {
+            return Err(anyhow!("Cannot invert a zero price"));
+        }
+        Ok(Self {
+            price: Decimal::ONE / self.price,
+            timestamp: self.timestamp,
+        })
+    }
+}
+
+/// Anything that can quote a market price for a symbol
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    /// Short name of the source used in logs
+    fn name(&self) -> &str;
+
+    /// Fetch the current price for `pair`, in the source's own symbol format
+    async fn price(&self, pair: &str) -> Result<PriceQuote>;
+}
+
+/// A fixed, manually configured price
+pub struct StaticPriceSource {
+    price: Decimal,
+}
+
+impl StaticPriceSource {
+    pub fn new(price: Decimal) -> Self {
+        Self { price }
+    }
+}
+
+#[async_trait]
+impl PriceSource for StaticPriceSource {
+    fn name(&self) -> &str {
+        "static"
+    }
+
+    async fn price(&self, _pair: &str) -> Result<PriceQuote> {
+        Ok(PriceQuote::now(self.price))
+    }
+}
+
+/// Exchange clients shared by every pair, so HTTP connections are reused
+#[derive(Clone)]
+pub struct PriceSources {
+    kraken: KrakenClient,
+    binance: Arc<BinanceClient>,
+    coinbase: Arc<CoinbaseClient>,
+    kraken_cache: Option<KrakenPriceCache>,
+}
+
+impl Default for PriceSources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PriceSources {
+    pub fn new() -> Self {
+        Self {
+            kraken: KrakenClient::new(),
+            binance: Arc::new(BinanceClient::new()),
+            coinbase: Arc::new(CoinbaseClient::new()),
+            kraken_cache: None,
+        }
+    }
+
+    /// Serve Kraken prices from the shared WebSocket cache
+    pub fn with_kraken_cache(mut self, cache: KrakenPriceCache) -> Self {
+        self.kraken_cache = Some(cache);
+        self
+    }
+
+    /// Bind the configured source to its market
+    pub fn reference_price(&self, config: &PriceSourceConfig) -> ReferencePrice {
+        let (source, pair, reverse): (Arc<dyn PriceSource>, _, _) = match config {
+            PriceSourceConfig::Kraken {
+                pair,
+                reverse,
+                mode,
+                rolling_window,
+                ws_symbol,
+            } => {
+                let mut source = KrakenSource::new(self.kraken.clone(), *mode, *rolling_window);
+                if let Some(cache) = &self.kraken_cache {
+                    source = source.with_cache(cache.clone(), ws_symbol.clone());
+                }
+                (Arc::new(source), pair.clone(), *reverse)
+            }
+            PriceSourceConfig::Binance { pair, reverse } => {
+                (self.binance.clone(), pair.clone(), *reverse)
+            }
+            PriceSourceConfig::Coinbase { pair, reverse } => {
+                (self.coinbase.clone(), pair.clone(), *reverse)
+            }
+            PriceSourceConfig::Static { price } => (
+                Arc::new(StaticPriceSource::new(*price)),
+                String::new(),
+                false,
+            ),
+            PriceSourceConfig::Cross { legs } => {
+                return ReferencePrice::Cross(
+                    legs.iter().map(|leg| self.reference_price(leg)).collect(),
+                );
+            }
+            PriceSourceConfig::Median {
+                sources,
+                max_spread_bps,
+                quorum,
+            } => {
+                let sources = sources.iter().map(|s| self.reference_price(s)).collect();
+                return ReferencePrice::Median(MedianOracle::new(
+                    sources,
+                    *max_spread_bps,
+                    *quorum,
+                ));
+            }
+        };
+        ReferencePrice::new(source, pair, reverse)
+    }
+}
+
+/// The reference price a pool is pegged to: a single source bound to its
+/// market, a cross rate composed of several, or an aggregate over several
+#[derive(Clone)]
+pub enum ReferencePrice {
+    Single {
+        source: Arc<dyn PriceSource>,
+        pair: String,
+        reverse: bool,
+    },
+    /// Product of the legs' prices
+    Cross(Vec<ReferencePrice>),
+    Median(MedianOracle),
+}
+
+impl ReferencePrice {
+    pub fn new(source: Arc<dyn PriceSource>, pair: impl Into<String>, reverse: bool) -> Self {
+        Self::Single {
+            source,
+            pair: pair.into(),
+            reverse,
+        }
+    }
+
+    /// Fetch the price of token0 in token1, applying the configured inversions.
+    /// Composite prices carry the timestamp of their oldest input.
+    pub fn quote(&self) -> BoxFuture<'_, Result<PriceQuote>> {
+        async move {
+            match self {
+                Self::Single {
+                    source,
+                    pair,
+                    reverse,
+                } => {
+                    let quote = source.price(pair).await?;
+                    if *reverse {
+                        quote.inverted()
+                    } else {
+                        Ok(quote)
+                    }
+                }
+                Self::Cross(legs) => {
+                    let quotes = try_join_all(legs.iter().map(|leg| leg.quote())).await?;
+                    let price = quotes.iter().map(|q| q.price).product();
+                    let timestamp = quotes
+                        .iter()
+                        .map(|q| q.timestamp)
+                        .min()
+                        .ok_or_else(|| anyhow!("Cross price has no legs"))?;
+                    Ok(PriceQuote { price, timestamp })
+                }
+                Self::Median(oracle) => Ok(oracle.quote().await?),
+            }
+        }
+        .boxed()
+    }
+
+    /// Human readable description, e.g. "kraken:ETHUSD"
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Single {
+                source,
+                pair,
+                reverse,
+            } => {
+                let mut description = format!("{}:{}", source.name(), pair);
+                if *reverse {
+                    description.push_str(" (inverted)");
+                }
+                description
+            }
+            Self::Cross(legs) => {
+                let legs: Vec<String> = legs.iter().map(|leg| leg.describe()).collect();
+                format!("cross[{}]", legs.join(" * "))
+            }
+            Self::Median(oracle) => oracle.describe(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn old_quotes_are_stale() {
+        let max_age = Duration::from_secs(60);
+        let fresh = PriceQuote::now(dec!(2500));
+        assert_eq!(fresh.check_age(max_age).unwrap(), fresh);
+        let old = PriceQuote {
+            timestamp: SystemTime::now() - Duration::from_secs(90),
+            ..fresh
+        };
+        let error = old.check_age(max_age).unwrap_err();
+        let stale = error.downcast_ref::<StalePriceError>().unwrap();
+        assert!(stale.age >= Duration::from_secs(90));
+        assert_eq!(stale.max_age, max_age);
+        // A clock running behind the source's doesn't make quotes stale
+        let ahead = PriceQuote {
+            timestamp: SystemTime::now() + Duration::from_secs(5),
+            ..fresh
+        };
+        assert!(ahead.check_age(max_age).is_ok());
+    }
+
+    #[tokio::test]
+    async fn cross_multiplies_legs_with_inversion() {
+        let sources = PriceSources::new();
+        let config: PriceSourceConfig = toml::from_str(
+            r#"
+            source = "cross"
+            legs = [
+                { source = "static", price = "60000" },
+                { source = "static", price = "0.0004" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let cross = sources.reference_price(&config);
+        assert_eq!(cross.quote().await.unwrap().price, dec!(24));
+
+        let btc_in_eth = ReferencePrice::Cross(vec![
+            ReferencePrice::new(
+                Arc::new(StaticPriceSource::new(dec!(60000))),
+                "XBTUSD",
+                false,
+            ),
+            ReferencePrice::new(Arc::new(StaticPriceSource::new(dec!(2500))), "ETHUSD", true),
+        ]);
+        assert_eq!(btc_in_eth.quote().await.unwrap().price, dec!(24));
+    }
+}
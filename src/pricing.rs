@@ -0,0 +1,518 @@
+use alloy::primitives::{U256, U512};
+use rust_decimal::{Decimal, dec};
+
+const BPS: Decimal = dec!(10000);
+const BPS_U: u64 = 10000;
+
+/// Direction of a swap against a UniswapV2 pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapDirection {
+    /// Sell token0, receive token1 (lowers the token0 price)
+    Token0ToToken1,
+    /// Sell token1, receive token0 (raises the token0 price)
+    Token1ToToken0,
+}
+
+/// A swap that moves the pool to a target price
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rebalance {
+    pub direction: SwapDirection,
+    /// Amount of the input token to swap, in raw token units
+    pub amount_in: U256,
+}
+
+/// An exact, non-negative ratio of raw token amounts, `num / den`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ratio {
+    pub num: U256,
+    pub den: U256,
+}
+
+impl Ratio {
+    /// Converts a human-unit price of token0 in token1 into the raw
+    /// `reserve1 / reserve0` ratio it corresponds to:
+    /// `price * 10^decimals1 / 10^decimals0`.
+    ///
+    /// Returns `None` for non-positive prices or if the scaling overflows.
+    pub fn from_price(price: Decimal, decimals0: u8, decimals1: u8) -> Option<Self> {
+        if price <= Decimal::ZERO {
+            return None;
+        }
+        let ten = U256::from(10);
+        let mantissa = U256::from(price.mantissa().unsigned_abs());
+        let num = mantissa.checked_mul(ten.checked_pow(U256::from(decimals1))?)?;
+        let den = ten.checked_pow(U256::from(price.scale() + decimals0 as u32))?;
+        Some(Self { num, den })
+    }
+
+    /// The reciprocal ratio, `den / num`
+    pub fn inverse(self) -> Self {
+        Self {
+            num: self.den,
+            den: self.num,
+        }
+    }
+}
+
+/// Converts a raw token amount into human units, dropping the least
+/// significant digits if the amount exceeds `Decimal`'s 96-bit mantissa.
+///
+/// Returns `None` only if the integer part itself doesn't fit.
+pub fn to_decimal(raw: U256, decimals: u8) -> Option<Decimal> {
+    const MAX_MANTISSA: U256 = U256::from_limbs([u64::MAX, u32::MAX as u64, 0, 0]);
+    const MAX_SCALE: u32 = 28;
+
+    let mut value = raw;
+    let mut scale = decimals as u32;
+    while scale > 0 && (value > MAX_MANTISSA || scale > MAX_SCALE) {
+        value /= U256::from(10);
+        scale -= 1;
+    }
+    if value > MAX_MANTISSA {
+        return None;
+    }
+    Some(Decimal::from_i128_with_scale(
+        value.to::<u128>() as i128,
+        scale,
+    ))
+}
+
+/// Converts an amount in human units into raw token units, rounding down.
+///
+/// Returns `None` for negative amounts or if the scaling overflows.
+pub fn from_decimal(value: Decimal, decimals: u8) -> Option<U256> {
+    if value.is_sign_negative() {
+        return None;
+    }
+    let ten = U256::from(10);
+    let mantissa = U256::from(value.mantissa().unsigned_abs());
+    let scaled = mantissa.checked_mul(ten.checked_pow(U256::from(decimals))?)?;
+    Some(scaled / ten.pow(U256::from(value.scale())))
+}
+
+/// Price of token0 denominated in token1 for the given raw reserves, or
+/// `None` if the pool is empty
+pub fn pool_price(reserve0: U256, reserve1: U256, decimals0: u8, decimals1: u8) -> Option<Decimal> {
+    let reserve0 = to_decimal(reserve0, decimals0)?;
+    let reserve1 = to_decimal(reserve1, decimals1)?;
+    reserve1.checked_div(reserve0)
+}
+
+/// Relative difference between the pool price and the reference price, in
+/// basis points, or `None` for a zero reference price. A deviation too large
+/// to represent, as on a drained pool, saturates at `Decimal::MAX`.
+pub fn deviation_bps(pool_price: Decimal, reference_price: Decimal) -> Option<Decimal> {
+    if reference_price.is_zero() {
+        return None;
+    }
+    Some(
+        pool_price
+            .checked_sub(reference_price)
+            .and_then(|difference| difference.checked_div(reference_price))
+            .and_then(|ratio| ratio.abs().checked_mul(BPS))
+            .unwrap_or(Decimal::MAX),
+    )
+}
+
+/// Price the pool should be moved to: the reference price pushed `overshoot_bps`
+/// past the peg in the direction of the move, so the next round-trip of noise
+/// doesn't immediately trigger another swap the other way.
+pub fn target_price(pool_price: Decimal, reference_price: Decimal, overshoot_bps: u32) -> Decimal {
+    let overshoot = Decimal::from(overshoot_bps) / BPS;
+    if reference_price > pool_price {
+        reference_price * (Decimal::ONE + overshoot)
+    } else {
+        reference_price * (Decimal::ONE - overshoot)
+    }
+}
+
+/// Expected profit of a swap net of gas, in input token units. The output is
+/// valued at `reference_price` and `gas_cost0` is given in token0 units.
+///
+/// Returns `None` if the amounts are too large to value.
+pub fn net_profit(
+    direction: SwapDirection,
+    amount_in: Decimal,
+    amount_out: Decimal,
+    reference_price: Decimal,
+    gas_cost0: Decimal,
+) -> Option<Decimal> {
+    match direction {
+        SwapDirection::Token0ToToken1 => amount_out
+            .checked_div(reference_price)?
+            .checked_sub(amount_in)?
+            .checked_sub(gas_cost0),
+        SwapDirection::Token1ToToken0 => amount_out
+            .checked_mul(reference_price)?
+            .checked_sub(amount_in)?
+            .checked_sub(gas_cost0.checked_mul(reference_price)?),
+    }
+}
+
+fn widen(value: U256) -> U512 {
+    U512::from(value)
+}
+
+/// Narrows a 512-bit intermediate back to 256 bits, saturating on overflow
+fn narrow(value: U512) -> U256 {
+    if value > widen(U256::MAX) {
+        U256::MAX
+    } else {
+        value.to()
+    }
+}
+
+/// Floor of the square root, by Newton's method
+fn sqrt(value: U512) -> U512 {
+    if value < U512::from(2) {
+        return value;
+    }
+    // 2^ceil(bits / 2) is always at or above the root
+    let mut root = U512::ONE << value.bit_len().div_ceil(2);
+    loop {
+        let next = (root + value / root) >> 1;
+        if next >= root {
+            return root;
+        }
+        root = next;
+    }
+}
+
+/// Output amount of a swap, mirroring `UniswapV2Library::getAmountOut`
+/// with a fee of `fee_bps` basis points taken from the input.
+pub fn amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, fee_bps: u32) -> U256 {
+    let amount_in_with_fee = widen(amount_in) * U512::from(BPS_U.saturating_sub(fee_bps as u64));
+    let denominator = widen(reserve_in) * U512::from(BPS_U) + amount_in_with_fee;
+    if denominator.is_zero() {
+        return U256::ZERO;
+    }
+    narrow(amount_in_with_fee * widen(reserve_out) / denominator)
+}
+
+/// Lowest acceptable output for a quoted swap given a slippage tolerance in basis points
+pub fn min_amount_out(quoted_out: U256, slippage_bps: u32) -> U256 {
+    let kept_bps = BPS_U.saturating_sub(slippage_bps as u64);
+    narrow(widen(quoted_out) * U512::from(kept_bps) / U512::from(BPS_U))
+}
+
+/// Computes the input amount that moves `reserve_in / reserve_out` to
+/// `target` once the swap settles, accounting for the LP fee.
+///
+/// With `g = 1 - fee` the reserves after swapping `dx` are
+/// `r_in + dx` and `r_in * r_out / (r_in + g * dx)`, so `dx` is the positive
+/// root of `g*dx^2 + (1 + g)*r_in*dx + r_in^2 - target*r_in*r_out = 0`.
+/// Everything is evaluated in 512-bit integers, which cannot overflow for
+/// uint112 reserves, and the result is rounded down.
+///
+/// Returns `None` if the pool is already at or beyond the target ratio, or
+/// if the required input doesn't fit in 256 bits.
+pub fn input_to_target(
+    reserve_in: U256,
+    reserve_out: U256,
+    target: Ratio,
+    fee_bps: u32,
+) -> Option<U256> {
+    if reserve_in.is_zero() || reserve_out.is_zero() || target.den.is_zero() {
+        return None;
+    }
+    if fee_bps as u64 >= BPS_U {
+        return None;
+    }
+    let (r_in, r_out) = (widen(reserve_in), widen(reserve_out));
+    let (num, den) = (widen(target.num), widen(target.den));
+    // Already there: r_in / r_out >= num / den
+    if r_in * den >= num * r_out {
+        return None;
+    }
+
+    // Scaled by 10000: G = 10000 * g
+    let bps = U512::from(BPS_U);
+    let gamma = U512::from(BPS_U - fee_bps as u64);
+    let fee = bps - gamma;
+
+    // discriminant = (10000 - G)^2 * r_in^2 + 4 * 10000 * G * r_in * r_out * num / den.
+    // Checked so that reserves outside the uint112 range fail instead of wrapping.
+    let fee_term = (fee * fee * r_in).checked_mul(r_in)?.checked_mul(den)?;
+    let price_term = (U512::from(4) * bps * gamma * r_in)
+        .checked_mul(r_out)?
+        .checked_mul(num)?;
+    let discriminant = fee_term.checked_add(price_term)? / den;
+
+    let root = sqrt(discriminant);
+    let offset = (bps + gamma) * r_in;
+    if root <= offset {
+        return None;
+    }
+    let amount = (root - offset) / (U512::from(2) * gamma);
+    if amount.is_zero() || amount > widen(U256::MAX) {
+        return None;
+    }
+    Some(amount.to())
+}
+
+/// Computes the swap that moves the raw pool ratio `reserve1 / reserve0` to
+/// `target`, or `None` if the pool is already there.
+pub fn rebalance(reserve0: U256, reserve1: U256, target: Ratio, fee_bps: u32) -> Option<Rebalance> {
+    if target.num.is_zero() || target.den.is_zero() {
+        return None;
+    }
+    let current = widen(reserve1) * widen(target.den);
+    let wanted = widen(target.num) * widen(reserve0);
+    if wanted > current {
+        input_to_target(reserve1, reserve0, target, fee_bps).map(|amount_in| Rebalance {
+            direction: SwapDirection::Token1ToToken0,
+            amount_in,
+        })
+    } else if wanted < current {
+        input_to_target(reserve0, reserve1, target.inverse(), fee_bps).map(|amount_in| Rebalance {
+            direction: SwapDirection::Token0ToToken1,
+            amount_in,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const MAX_RESERVE: u128 = (1 << 112) - 1;
+
+    fn e18(value: u64) -> U256 {
+        U256::from(value) * U256::from(10).pow(U256::from(18))
+    }
+
+    fn e6(value: u64) -> U256 {
+        U256::from(value) * U256::from(1_000_000)
+    }
+
+    /// Applies the rebalance to the reserves and returns the new reserves
+    fn settle(reserve0: U256, reserve1: U256, rebalance: Rebalance, fee_bps: u32) -> (U256, U256) {
+        match rebalance.direction {
+            SwapDirection::Token1ToToken0 => {
+                let out = amount_out(rebalance.amount_in, reserve1, reserve0, fee_bps);
+                (reserve0 - out, reserve1.saturating_add(rebalance.amount_in))
+            }
+            SwapDirection::Token0ToToken1 => {
+                let out = amount_out(rebalance.amount_in, reserve0, reserve1, fee_bps);
+                (reserve0.saturating_add(rebalance.amount_in), reserve1 - out)
+            }
+        }
+    }
+
+    fn assert_price_close(reserve0: U256, reserve1: U256, target: Decimal) {
+        let price = pool_price(reserve0, reserve1, 18, 6).unwrap();
+        assert!(
+            deviation_bps(price, target).unwrap() < dec!(0.001),
+            "expected {target}, got {price}"
+        );
+    }
+
+    #[test]
+    fn net_profit_values_output_at_reference() {
+        // Sell 1 ETH for 2550 USDT while ETH is worth 2500: gain 0.02 ETH,
+        // minus 0.005 ETH of gas
+        let profit = net_profit(
+            SwapDirection::Token0ToToken1,
+            dec!(1),
+            dec!(2550),
+            dec!(2500),
+            dec!(0.005),
+        );
+        assert_eq!(profit, Some(dec!(0.015)));
+
+        // Buy 1.02 ETH for 2500 USDT: gain 50 USDT, minus 0.005 ETH = 12.5 USDT of gas
+        let profit = net_profit(
+            SwapDirection::Token1ToToken0,
+            dec!(2500),
+            dec!(1.02),
+            dec!(2500),
+            dec!(0.005),
+        );
+        assert_eq!(profit, Some(dec!(37.5)));
+    }
+
+    #[test]
+    fn feeless_input_matches_closed_form() {
+        // 1000/1000 pool pushed to 1.21: sqrt(1.21 * 1e6) - 1000 = 100
+        let target = Ratio::from_price(dec!(1.21), 18, 18).unwrap();
+        let amount = input_to_target(e18(1000), e18(1000), target, 0).unwrap();
+        assert_eq!(amount, e18(100));
+    }
+
+    #[test]
+    fn fee_increases_required_input() {
+        let target = Ratio::from_price(dec!(1.21), 18, 18).unwrap();
+        let without_fee = input_to_target(e18(1000), e18(1000), target, 0).unwrap();
+        let with_fee = input_to_target(e18(1000), e18(1000), target, 30).unwrap();
+        assert!(with_fee > without_fee);
+        assert_eq!(with_fee, U256::from(100_150235611442938992_u128));
+    }
+
+    #[test]
+    fn rebalance_up_hits_target_price() {
+        // ETH/USDT style pool: 500 ETH, 1_250_000 USDT -> price 2500
+        let (r0, r1) = (e18(500), e6(1_250_000));
+        let target = dec!(2600);
+        let rebalance = rebalance(r0, r1, Ratio::from_price(target, 18, 6).unwrap(), 30).unwrap();
+        assert_eq!(rebalance.direction, SwapDirection::Token1ToToken0);
+        let (r0, r1) = settle(r0, r1, rebalance, 30);
+        assert_price_close(r0, r1, target);
+    }
+
+    #[test]
+    fn rebalance_down_hits_target_price() {
+        let (r0, r1) = (e18(500), e6(1_250_000));
+        let target = dec!(2400);
+        let rebalance = rebalance(r0, r1, Ratio::from_price(target, 18, 6).unwrap(), 30).unwrap();
+        assert_eq!(rebalance.direction, SwapDirection::Token0ToToken1);
+        let (r0, r1) = settle(r0, r1, rebalance, 30);
+        assert_price_close(r0, r1, target);
+    }
+
+    #[test]
+    fn feeless_sizing_undershoots_with_fee() {
+        // The old sqrt(k * p) - r1 sizing leaves the pool short of the target
+        let (r0, r1) = (e18(500), e6(1_250_000));
+        let target = Ratio::from_price(dec!(2600), 18, 6).unwrap();
+        let naive = Rebalance {
+            direction: SwapDirection::Token1ToToken0,
+            amount_in: input_to_target(r1, r0, target, 0).unwrap(),
+        };
+        let (r0, r1) = settle(r0, r1, naive, 30);
+        assert!(pool_price(r0, r1, 18, 6).unwrap() < dec!(2599.9));
+    }
+
+    #[test]
+    fn no_rebalance_at_target() {
+        let target = Ratio::from_price(dec!(2), 18, 18).unwrap();
+        assert_eq!(rebalance(e18(1000), e18(2000), target, 30), None);
+        let target = Ratio::from_price(dec!(0.5), 18, 18).unwrap();
+        assert_eq!(input_to_target(e18(1000), e18(1000), target, 30), None);
+    }
+
+    #[test]
+    fn deviation_is_symmetric_in_direction() {
+        assert_eq!(deviation_bps(dec!(2525), dec!(2500)), Some(dec!(100)));
+        assert_eq!(deviation_bps(dec!(2475), dec!(2500)), Some(dec!(100)));
+        assert_eq!(deviation_bps(dec!(2500), dec!(2500)), Some(Decimal::ZERO));
+        assert_eq!(deviation_bps(dec!(2500), Decimal::ZERO), None);
+    }
+
+    #[test]
+    fn deviation_of_drained_pool_saturates() {
+        // 1 wei of token0 against 10 million token1
+        let pool_price = pool_price(U256::from(1), e18(10_000_000), 18, 18).unwrap();
+        assert_eq!(deviation_bps(pool_price, dec!(1)), Some(Decimal::MAX));
+        assert_eq!(deviation_bps(dec!(1), Decimal::MAX), Some(dec!(10000)));
+        assert_eq!(deviation_bps(Decimal::MAX, dec!(0.5)), Some(Decimal::MAX));
+    }
+
+    #[test]
+    fn target_overshoots_in_direction_of_move() {
+        assert_eq!(target_price(dec!(2400), dec!(2500), 10), dec!(2502.5));
+        assert_eq!(target_price(dec!(2600), dec!(2500), 10), dec!(2497.5));
+        assert_eq!(target_price(dec!(2600), dec!(2500), 0), dec!(2500));
+    }
+
+    #[test]
+    fn min_amount_out_applies_tolerance() {
+        assert_eq!(
+            min_amount_out(U256::from(1_000_000), 50),
+            U256::from(995_000)
+        );
+        assert_eq!(
+            min_amount_out(U256::from(1_000_000), 0),
+            U256::from(1_000_000)
+        );
+        assert_eq!(min_amount_out(U256::from(999), 10), U256::from(998));
+        assert_eq!(min_amount_out(U256::MAX, 0), U256::MAX);
+    }
+
+    #[test]
+    fn amount_out_matches_router_formula() {
+        // 1e18 into a 100e18/100e18 pool with 0.3% fee: 997e18 * 100e18 / (100_000e18 + 997e18)
+        let out = amount_out(e18(1), e18(100), e18(100), 30);
+        assert_eq!(out, U256::from(987158034397061298_u128));
+    }
+
+    #[test]
+    fn from_decimal_scales_and_rounds_down() {
+        assert_eq!(from_decimal(dec!(1.5), 6), Some(U256::from(1_500_000)));
+        assert_eq!(from_decimal(dec!(0.0000001), 6), Some(U256::ZERO));
+        assert_eq!(from_decimal(dec!(2), 18), Some(e18(2)));
+        assert_eq!(from_decimal(dec!(-1), 18), None);
+    }
+
+    #[test]
+    fn large_reserves_convert_without_panicking() {
+        let max = U256::from(MAX_RESERVE);
+        assert_eq!(pool_price(max, max, 18, 18).unwrap(), Decimal::ONE);
+        assert!(to_decimal(max, 0).is_none());
+        assert_eq!(to_decimal(e18(5), 18).unwrap(), dec!(5));
+        assert_eq!(pool_price(U256::ZERO, e18(5), 18, 18), None);
+    }
+
+    #[test]
+    fn sqrt_is_floor() {
+        for value in [0u64, 1, 2, 3, 4, 15, 16, 17, 1 << 40, u64::MAX] {
+            let value = U512::from(value);
+            let root = sqrt(value);
+            assert!(root * root <= value);
+            assert!((root + U512::ONE) * (root + U512::ONE) > value);
+        }
+    }
+
+    fn reserve() -> impl Strategy<Value = U256> {
+        (1..=MAX_RESERVE).prop_map(U256::from)
+    }
+
+    fn price() -> impl Strategy<Value = Decimal> {
+        (1..=u64::MAX, 0..=28u32).prop_map(|(m, s)| Decimal::from_i128_with_scale(m as i128, s))
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics_across_uint112_range(
+            reserve0 in reserve(),
+            reserve1 in reserve(),
+            price in price(),
+            decimals0 in 0..=36u8,
+            decimals1 in 0..=36u8,
+            fee_bps in 0..10000u32,
+        ) {
+            let _ = pool_price(reserve0, reserve1, decimals0, decimals1);
+            if let Some(target) = Ratio::from_price(price, decimals0, decimals1)
+                && let Some(rebalance) = rebalance(reserve0, reserve1, target, fee_bps)
+            {
+                prop_assert!(!rebalance.amount_in.is_zero());
+                let _ = settle(reserve0, reserve1, rebalance, fee_bps);
+            }
+        }
+
+        #[test]
+        fn settles_at_target_ratio(
+            reserve0 in 1_000_000_000u128..=MAX_RESERVE,
+            reserve1 in 1_000_000_000u128..=MAX_RESERVE,
+            factor_permille in 10u64..100_000,
+            fee_bps in 0..100u32,
+        ) {
+            let (reserve0, reserve1) = (U256::from(reserve0), U256::from(reserve1));
+            // Target within 100x either way of the current ratio
+            let target = Ratio {
+                num: reserve1 * U256::from(factor_permille),
+                den: reserve0 * U256::from(1000),
+            };
+            if let Some(rebalance) = rebalance(reserve0, reserve1, target, fee_bps) {
+                let (r0, r1) = settle(reserve0, reserve1, rebalance, fee_bps);
+                // |r1 / r0 - num / den| <= (num / den) / 10_000
+                let settled = widen(r1) * widen(target.den);
+                let wanted = widen(target.num) * widen(r0);
+                let diff = if settled > wanted { settled - wanted } else { wanted - settled };
+                prop_assert!(diff * U512::from(10_000) <= wanted);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::config::RetryConfig;
+use std::time::Duration;
+
+/// A connection or worker that stayed up this long is healthy again, so its
+/// next failure backs off from the shortest delay
+pub const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// What to do after a transient failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// Try again after this delay
+    After(Duration),
+    /// Too many consecutive failures: stop calling out for this long
+    CircuitOpen(Duration),
+}
+
+impl Retry {
+    /// How long to wait either way, for callers without a circuit to open
+    pub fn delay(self) -> Duration {
+        match self {
+            Retry::After(delay) | Retry::CircuitOpen(delay) => delay,
+        }
+    }
+}
+
+/// Exponential backoff with jitter over consecutive failures, opening a
+/// circuit once `circuit_threshold` of them happen in a row
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: RetryConfig,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(config: RetryConfig) -> Self {
+        Self {
+            config,
+            failures: 0,
+        }
+    }
+
+    /// Reset after a call went through
+    pub fn success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Record that something which ran for `ran_for` stopped, starting over
+    /// from the shortest delay if it had been up for [`STABLE_AFTER`]
+    pub fn stopped(&mut self, ran_for: Duration) -> Retry {
+        if ran_for >= STABLE_AFTER {
+            self.success();
+        }
+        self.failure()
+    }
+
+    /// Record a failure and decide how long to wait
+    pub fn failure(&mut self) -> Retry {
+        self.failures += 1;
+        if self.failures >= self.config.circuit_threshold {
+            // Half-open once the pause ends: one more failure reopens it
+            self.failures = self.config.circuit_threshold - 1;
+            return Retry::CircuitOpen(Duration::from_secs(self.config.circuit_open_secs));
+        }
+        let jitter = if self.config.jitter > 0.0 {
+            rand::random_range(-self.config.jitter..=self.config.jitter)
+        } else {
+            0.0
+        };
+        Retry::After(self.delay().mul_f64(1.0 + jitter).min(self.max_delay()))
+    }
+
+    /// Delay before jitter for the current failure count
+    fn delay(&self) -> Duration {
+        let factor = self
+            .config
+            .multiplier
+            .powi(self.failures.saturating_sub(1) as i32);
+        let secs = self.config.initial_delay_ms as f64 / 1000.0 * factor;
+        Duration::try_from_secs_f64(secs)
+            .map_or(self.max_delay(), |delay| delay.min(self.max_delay()))
+    }
+
+    fn max_delay(&self) -> Duration {
+        Duration::from_secs(self.config.max_delay_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(jitter: f64) -> Backoff {
+        Backoff::new(RetryConfig {
+            initial_delay_ms: 500,
+            max_delay_secs: 4,
+            multiplier: 2.0,
+            jitter,
+            circuit_threshold: 6,
+            circuit_open_secs: 60,
+        })
+    }
+
+    #[test]
+    fn grows_exponentially_up_to_max() {
+        let mut backoff = backoff(0.0);
+        let delays: Vec<Retry> = (0..5).map(|_| backoff.failure()).collect();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 4000, 4000].map(|ms| Retry::After(Duration::from_millis(ms)))
+        );
+        backoff.success();
+        assert_eq!(backoff.failure(), Retry::After(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn opens_circuit_after_threshold() {
+        let mut backoff = backoff(0.0);
+        for _ in 0..5 {
+            assert!(matches!(backoff.failure(), Retry::After(_)));
+        }
+        let open = Retry::CircuitOpen(Duration::from_secs(60));
+        assert_eq!(backoff.failure(), open);
+        assert_eq!(open.delay(), Duration::from_secs(60));
+        // Half-open: the next failure reopens straight away
+        assert_eq!(backoff.failure(), open);
+        backoff.success();
+        assert!(matches!(backoff.failure(), Retry::After(_)));
+    }
+
+    #[test]
+    fn stable_run_resets_backoff() {
+        let mut backoff = backoff(0.0);
+        backoff.failure();
+        let short = STABLE_AFTER - Duration::from_secs(1);
+        assert_eq!(
+            backoff.stopped(short),
+            Retry::After(Duration::from_millis(1000))
+        );
+        assert_eq!(
+            backoff.stopped(STABLE_AFTER),
+            Retry::After(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let mut backoff = backoff(0.25);
+        for _ in 0..100 {
+            backoff.success();
+            let Retry::After(delay) = backoff.failure() else {
+                panic!("circuit opened early");
+            };
+            assert!(delay >= Duration::from_millis(375) && delay <= Duration::from_millis(625));
+        }
+    }
+}
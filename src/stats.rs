@@ -0,0 +1,74 @@
+use crate::breaker::BreakerState;
+use crate::limits::VolumeWindow;
+use crate::paper::PaperAccount;
+use crate::pricing::SwapDirection;
+use alloy::primitives::U256;
+use rust_decimal::Decimal;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Running totals for one pair, kept across worker restarts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PairTotals {
+    pub swaps: u64,
+    pub failed_swaps: u64,
+    /// Fees paid for confirmed swaps, in wei
+    pub gas_cost: U256,
+    pub restarts: u64,
+    /// Dry runs only: swaps that were simulated instead of sent
+    pub simulated_swaps: u64,
+    /// Dry runs only: what the pair's simulated swaps moved
+    pub paper: Option<PaperAccount>,
+    /// Dry runs only: value gained by the simulated swaps as of the last
+    /// one, in token1 units
+    pub paper_pnl: Option<Decimal>,
+    /// Recent swap inputs of token0, for the daily volume cap
+    pub volume0: VolumeWindow,
+    /// Recent swap inputs of token1, for the daily volume cap
+    pub volume1: VolumeWindow,
+    /// Kept here so a restarted worker stays halted
+    pub breaker: BreakerState,
+}
+
+impl PairTotals {
+    /// Volume window of the token a swap in `direction` sells
+    pub fn volume_mut(&mut self, direction: SwapDirection) -> &mut VolumeWindow {
+        match direction {
+            SwapDirection::Token0ToToken1 => &mut self.volume0,
+            SwapDirection::Token1ToToken0 => &mut self.volume1,
+        }
+    }
+}
+
+impl fmt::Display for PairTotals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} swaps confirmed, {} failed, {} wei gas, {} restarts",
+            self.swaps, self.failed_swaps, self.gas_cost, self.restarts
+        )?;
+        if self.paper.is_some() {
+            write!(f, ", {} swaps simulated", self.simulated_swaps)?;
+            if let Some(pnl) = self.paper_pnl {
+                write!(f, ", paper PnL {} token1", pnl)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shared handle to a pair's [`PairTotals`]
+#[derive(Debug, Clone, Default)]
+pub struct PairStats {
+    totals: Arc<Mutex<PairTotals>>,
+}
+
+impl PairStats {
+    pub fn record<R>(&self, update: impl FnOnce(&mut PairTotals) -> R) -> R {
+        update(&mut self.totals.lock().expect("stats lock poisoned"))
+    }
+
+    pub fn totals(&self) -> PairTotals {
+        self.totals.lock().expect("stats lock poisoned").clone()
+    }
+}
@@ -0,0 +1,447 @@
+use crate::gas::{GasFees, GasStrategy};
+use alloy::network::TransactionBuilder;
+use alloy::primitives::{Address, TxHash, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use anyhow::{Result, anyhow};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+/// How many times a transaction is resent after a nonce conflict
+const MAX_NONCE_RETRIES: usize = 3;
+
+/// Gas limit of a plain value transfer, used for cancellations
+const TRANSFER_GAS: u64 = 21_000;
+
+/// Why a pending transaction could not be replaced
+#[derive(Debug, Error)]
+pub enum ReplaceError {
+    #[error("Replacement for nonce {nonce} rejected as underpriced")]
+    Underpriced { nonce: u64 },
+    /// Fees can't be raised enough without exceeding `max_fee_per_gas`
+    #[error("Fee cap reached, cannot outbid the pending transaction with nonce {nonce}")]
+    FeeCapReached { nonce: u64 },
+}
+
+/// A transaction accepted by the node
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubmittedTx {
+    pub tx_hash: TxHash,
+    pub nonce: u64,
+    /// The request as sent, including nonce and fees, so it can be replaced
+    pub request: TransactionRequest,
+}
+
+enum Command {
+    /// Send with the next free nonce
+    Submit(TransactionRequest),
+    /// Resend with the nonce already set on the request and bumped fees
+    Replace {
+        tx: TransactionRequest,
+        bump_percent: u64,
+        /// Hash of the pending version
+        previous: TxHash,
+    },
+}
+
+struct Submission {
+    command: Command,
+    respond: oneshot::Sender<Result<SubmittedTx>>,
+}
+
+/// Handle to the service that signs and broadcasts every transaction of the
+/// wallet. Submissions from all pair workers are queued and sent one at a
+/// time, so each gets the next nonce and concurrent workers never collide.
+#[derive(Clone)]
+pub struct TxSubmitter {
+    sender: mpsc::Sender<Submission>,
+}
+
+impl TxSubmitter {
+    /// Spawn the submission service for `wallet`. `provider` must sign for it.
+    pub fn spawn<P: Provider + 'static>(provider: P, wallet: Address, gas: GasStrategy) -> Self {
+        let (sender, receiver) = mpsc::channel(64);
+        let service = SubmissionService {
+            provider,
+            wallet,
+            gas,
+            next_nonce: None,
+        };
+        tokio::spawn(service.run(receiver));
+        Self { sender }
+    }
+
+    /// Queue a transaction and wait until the node accepts it
+    pub async fn submit(&self, tx: TransactionRequest) -> Result<SubmittedTx> {
+        self.queue(Command::Submit(tx)).await
+    }
+
+    /// Resend a pending transaction with the same nonce and fees raised by at
+    /// least `bump_percent`, so the node accepts it as a replacement
+    pub async fn replace(&self, previous: &SubmittedTx, bump_percent: u64) -> Result<SubmittedTx> {
+        self.queue(Command::Replace {
+            tx: previous.request.clone(),
+            bump_percent,
+            previous: previous.tx_hash,
+        })
+        .await
+    }
+
+    /// Replace a pending transaction with a zero-value transfer to ourselves,
+    /// freeing its nonce without executing it
+    pub async fn cancel(&self, previous: &SubmittedTx, bump_percent: u64) -> Result<SubmittedTx> {
+        let mut tx = TransactionRequest::default()
+            .with_to(previous.request.from.unwrap_or_default())
+            .with_value(U256::ZERO)
+            .with_gas_limit(TRANSFER_GAS)
+            .with_nonce(previous.nonce);
+        tx.max_fee_per_gas = previous.request.max_fee_per_gas;
+        tx.max_priority_fee_per_gas = previous.request.max_priority_fee_per_gas;
+        tx.gas_price = previous.request.gas_price;
+        self.queue(Command::Replace {
+            tx,
+            bump_percent,
+            previous: previous.tx_hash,
+        })
+        .await
+    }
+
+    async fn queue(&self, command: Command) -> Result<SubmittedTx> {
+        let (respond, response) = oneshot::channel();
+        self.sender
+            .send(Submission { command, respond })
+            .await
+            .map_err(|_| anyhow!("Transaction submitter has stopped"))?;
+        response
+            .await
+            .map_err(|_| anyhow!("Transaction submitter dropped the request"))?
+    }
+}
+
+struct SubmissionService<P> {
+    provider: P,
+    wallet: Address,
+    gas: GasStrategy,
+    /// Next nonce to use; `None` forces a resync from the chain
+    next_nonce: Option<u64>,
+}
+
+impl<P: Provider> SubmissionService<P> {
+    async fn run(mut self, mut receiver: mpsc::Receiver<Submission>) {
+        while let Some(submission) = receiver.recv().await {
+            let result = match submission.command {
+                Command::Submit(tx) => self.send(tx).await,
+                Command::Replace {
+                    tx,
+                    bump_percent,
+                    previous,
+                } => self.replace(tx, bump_percent, previous).await,
+            };
+            // The worker may have given up waiting, nothing to do then
+            let _ = submission.respond.send(result);
+        }
+    }
+
+    async fn nonce(&mut self) -> Result<u64> {
+        match self.next_nonce {
+            Some(nonce) => Ok(nonce),
+            None => {
+                let nonce = self
+                    .provider
+                    .get_transaction_count(self.wallet)
+                    .pending()
+                    .await?;
+                info!("Synced wallet nonce from chain: {}", nonce);
+                self.next_nonce = Some(nonce);
+                Ok(nonce)
+            }
+        }
+    }
+
+    async fn send(&mut self, tx: TransactionRequest) -> Result<SubmittedTx> {
+        let mut tx = tx.with_from(self.wallet);
+        // Fees are set here rather than by the provider so that a replacement
+        // knows what it has to outbid
+        if GasFees::of(&tx).is_none() {
+            let estimate = self.gas.estimate(&self.provider).await?;
+            self.gas.ensure_affordable(&estimate)?;
+            tx = estimate.fees.apply(tx);
+        }
+        if tx.gas.is_none()
+            && let Some(gas_limit) = self.gas.gas_limit()
+        {
+            tx = tx.with_gas_limit(gas_limit);
+        }
+
+        let mut attempt = 0;
+        loop {
+            let nonce = self.nonce().await?;
+            let request = tx.clone().with_nonce(nonce);
+            match self.provider.send_transaction(request.clone()).await {
+                Ok(pending) => {
+                    self.next_nonce = Some(nonce + 1);
+                    return Ok(SubmittedTx {
+                        tx_hash: *pending.tx_hash(),
+                        nonce,
+                        request,
+                    });
+                }
+                Err(e) => {
+                    // Whatever happened, the cached nonce can no longer be trusted
+                    self.next_nonce = None;
+                    attempt += 1;
+                    if rejection(&e.to_string()) == Some(Rejection::BadNonce)
+                        && attempt <= MAX_NONCE_RETRIES
+                    {
+                        warn!("Nonce {} rejected ({}), resyncing from chain", nonce, e);
+                        continue;
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    async fn replace(
+        &mut self,
+        tx: TransactionRequest,
+        bump_percent: u64,
+        previous: TxHash,
+    ) -> Result<SubmittedTx> {
+        let nonce = tx
+            .nonce
+            .ok_or_else(|| anyhow!("Replacement transaction has no nonce"))?;
+        let current = self.gas.estimate(&self.provider).await?;
+        let fees = match GasFees::of(&tx) {
+            Some(previous) => {
+                let fees = self.gas.cap(
+                    bump_fees(previous, bump_percent, current.fees),
+                    current.network_fee,
+                )?;
+                // Nodes reject anything less as underpriced
+                if !outbids(fees, previous, bump_percent) {
+                    return Err(ReplaceError::FeeCapReached { nonce }.into());
+                }
+                fees
+            }
+            None => current.fees,
+        };
+        let request = fees.apply(tx.with_from(self.wallet));
+        // The nonce stays reserved by the original whatever happens here, so
+        // the cached nonce is left alone
+        match self.provider.send_transaction(request.clone()).await {
+            Ok(pending) => Ok(SubmittedTx {
+                tx_hash: *pending.tx_hash(),
+                nonce,
+                request,
+            }),
+            Err(e) => match rejection(&e.to_string()) {
+                // Identical to the pending version, which is still the one to track
+                Some(Rejection::AlreadyKnown) => Ok(SubmittedTx {
+                    tx_hash: previous,
+                    nonce,
+                    request,
+                }),
+                Some(Rejection::Underpriced) => Err(ReplaceError::Underpriced { nonce }.into()),
+                _ => Err(e.into()),
+            },
+        }
+    }
+}
+
+fn bump(fee: u128, bump_percent: u64) -> u128 {
+    fee + fee * u128::from(bump_percent) / 100
+}
+
+/// Raise `previous` fees by `bump_percent`, or to the `current` network
+/// estimate if that is higher
+fn bump_fees(previous: GasFees, bump_percent: u64, current: GasFees) -> GasFees {
+    let bump = |fee: u128| bump(fee, bump_percent);
+    match (previous, current) {
+        (GasFees::Legacy { gas_price }, GasFees::Legacy { gas_price: current }) => {
+            GasFees::Legacy {
+                gas_price: bump(gas_price).max(current),
+            }
+        }
+        (
+            GasFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            },
+            GasFees::Eip1559 {
+                max_fee_per_gas: current_max_fee,
+                max_priority_fee_per_gas: current_priority,
+            },
+        ) => {
+            let priority = bump(max_priority_fee_per_gas).max(current_priority);
+            GasFees::Eip1559 {
+                max_fee_per_gas: bump(max_fee_per_gas).max(current_max_fee).max(priority),
+                max_priority_fee_per_gas: priority,
+            }
+        }
+        // The transaction type changed, nothing to outbid
+        _ => current,
+    }
+}
+
+/// Whether `fees` are at least `bump_percent` above `previous`, as a
+/// replacement must be
+fn outbids(fees: GasFees, previous: GasFees, bump_percent: u64) -> bool {
+    match (fees, previous) {
+        (
+            GasFees::Legacy { gas_price },
+            GasFees::Legacy {
+                gas_price: previous,
+            },
+        ) => gas_price >= bump(previous, bump_percent),
+        (
+            GasFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            },
+            GasFees::Eip1559 {
+                max_fee_per_gas: previous_max_fee,
+                max_priority_fee_per_gas: previous_priority,
+            },
+        ) => {
+            max_fee_per_gas >= bump(previous_max_fee, bump_percent)
+                && max_priority_fee_per_gas >= bump(previous_priority, bump_percent)
+        }
+        // Switching transaction type is a different fee market
+        _ => true,
+    }
+}
+
+/// Node rejections that are handled rather than passed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rejection {
+    /// The nonce was already used, or is ahead of the account's next one
+    BadNonce,
+    /// The node already has this exact transaction
+    AlreadyKnown,
+    /// A pending transaction with the same nonce pays at least as much
+    Underpriced,
+}
+
+fn rejection(message: &str) -> Option<Rejection> {
+    let message = message.to_lowercase();
+    let contains = |needles: &[&str]| needles.iter().any(|needle| message.contains(needle));
+    if contains(&[
+        "nonce too low",
+        "nonce is too low",
+        "nonce too high",
+        "nonce is too high",
+        "invalid nonce",
+    ]) {
+        Some(Rejection::BadNonce)
+    } else if contains(&["already known", "known transaction"]) {
+        Some(Rejection::AlreadyKnown)
+    } else if contains(&["underpriced"]) {
+        Some(Rejection::Underpriced)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GasConfig;
+
+    #[test]
+    fn classifies_node_rejections() {
+        assert_eq!(
+            rejection("server returned an error response: error code -32000: nonce too low"),
+            Some(Rejection::BadNonce)
+        );
+        assert_eq!(
+            rejection("error code -32000: nonce too high"),
+            Some(Rejection::BadNonce)
+        );
+        assert_eq!(
+            rejection("error code -32000: already known"),
+            Some(Rejection::AlreadyKnown)
+        );
+        assert_eq!(
+            rejection("Replacement transaction underpriced"),
+            Some(Rejection::Underpriced)
+        );
+        assert_eq!(
+            rejection("transaction underpriced: tip needed 1, tip permitted 0"),
+            Some(Rejection::Underpriced)
+        );
+        assert_eq!(
+            rejection("insufficient funds for gas * price + value"),
+            None
+        );
+    }
+
+    #[test]
+    fn bumps_fees_above_previous_and_network() {
+        let previous = GasFees::Eip1559 {
+            max_fee_per_gas: 1_000,
+            max_priority_fee_per_gas: 100,
+        };
+        let bumped = bump_fees(
+            previous,
+            15,
+            GasFees::Eip1559 {
+                max_fee_per_gas: 900,
+                max_priority_fee_per_gas: 50,
+            },
+        );
+        assert_eq!(
+            bumped,
+            GasFees::Eip1559 {
+                max_fee_per_gas: 1_150,
+                max_priority_fee_per_gas: 115,
+            }
+        );
+
+        // A network that moved further than the bump wins
+        let current = GasFees::Eip1559 {
+            max_fee_per_gas: 2_000,
+            max_priority_fee_per_gas: 300,
+        };
+        assert_eq!(bump_fees(previous, 15, current), current);
+
+        assert_eq!(
+            bump_fees(
+                GasFees::Legacy { gas_price: 1_000 },
+                10,
+                GasFees::Legacy { gas_price: 900 }
+            ),
+            GasFees::Legacy { gas_price: 1_100 }
+        );
+    }
+
+    #[test]
+    fn capped_fees_must_still_outbid() {
+        let previous = GasFees::Eip1559 {
+            max_fee_per_gas: 1_000,
+            max_priority_fee_per_gas: 100,
+        };
+        let current = GasFees::Eip1559 {
+            max_fee_per_gas: 900,
+            max_priority_fee_per_gas: 50,
+        };
+        let capped = |cap| {
+            GasStrategy::new(GasConfig {
+                max_fee_per_gas: Some(cap),
+                ..GasConfig::default()
+            })
+            .cap(bump_fees(previous, 15, current), 400)
+            .unwrap()
+        };
+        assert!(outbids(capped(2_000), previous, 15));
+        assert!(!outbids(capped(1_100), previous, 15));
+        // Already at the cap, so the replacement would be identical
+        assert!(!outbids(capped(1_000), previous, 15));
+        assert!(!outbids(
+            GasFees::Legacy { gas_price: 1_099 },
+            GasFees::Legacy { gas_price: 1_000 },
+            10
+        ));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::config::RetryConfig;
+use crate::error::BotError;
+use crate::kuma::{KumaPushClient, KumaStatus};
+use crate::retry::Backoff;
+use crate::stats::PairStats;
+use std::any::Any;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinError;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// Keeps pair workers running: whenever one returns, fails or panics it is
+/// reported to Kuma and restarted with backoff, without touching the others.
+/// Nothing is restarted once `shutdown` is cancelled.
+#[derive(Clone)]
+pub struct Supervisor {
+    kuma: Arc<KumaPushClient>,
+    retry: RetryConfig,
+    shutdown: CancellationToken,
+}
+
+impl Supervisor {
+    pub fn new(kuma: Arc<KumaPushClient>, retry: RetryConfig, shutdown: CancellationToken) -> Self {
+        Self {
+            kuma,
+            retry,
+            shutdown,
+        }
+    }
+
+    /// Run the worker built by `start` until shutdown, restarting it whenever
+    /// it stops
+    pub async fn supervise<F, Fut>(
+        self,
+        name: String,
+        kuma_push_id: String,
+        stats: PairStats,
+        start: F,
+    ) where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<(), BotError>> + Send + 'static,
+    {
+        let mut backoff = Backoff::new(self.retry.clone());
+        loop {
+            let started = Instant::now();
+            let reason = match tokio::spawn(start()).await {
+                Ok(Ok(())) => "exited".to_string(),
+                Ok(Err(e)) => format!("failed: {}", e),
+                Err(e) => describe_join_error(e),
+            };
+            if self.shutdown.is_cancelled() {
+                info!("{}: worker {}", name, reason);
+                return;
+            }
+            stats.record(|totals| totals.restarts += 1);
+
+            let delay = backoff.stopped(started.elapsed()).delay();
+            let msg = format!(
+                "Worker {}, restarting in {}s",
+                reason,
+                delay.as_secs().max(1)
+            );
+            error!("{}: {}", name, msg);
+            if let Err(e) = self
+                .kuma
+                .push(&kuma_push_id, KumaStatus::Down, Some(&msg))
+                .await
+            {
+                error!("Failed to send status update to Kuma push: {}", e);
+            }
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = self.shutdown.cancelled() => return,
+            }
+            info!("{}: restarting worker", name);
+        }
+    }
+}
+
+fn describe_join_error(error: JoinError) -> String {
+    match error.try_into_panic() {
+        Ok(payload) => format!("panicked: {}", panic_message(payload.as_ref())),
+        Err(error) => format!("was cancelled: {}", error),
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn describes_panics() {
+        let error = tokio::spawn(async { panic!("decimals call failed") })
+            .await
+            .unwrap_err();
+        assert_eq!(describe_join_error(error), "panicked: decimals call failed");
+
+        let error = tokio::spawn(async { panic!("{} failed", "decimals") })
+            .await
+            .unwrap_err();
+        assert_eq!(describe_join_error(error), "panicked: decimals failed");
+    }
+
+    #[tokio::test]
+    async fn does_not_restart_after_shutdown() {
+        let kuma = Arc::new(KumaPushClient::new(
+            reqwest::Url::parse("http://127.0.0.1:9").unwrap(),
+        ));
+        let shutdown = CancellationToken::new();
+        let supervisor = Supervisor::new(kuma, RetryConfig::default(), shutdown.clone());
+        let stats = PairStats::default();
+        let starts = Arc::new(std::sync::atomic::AtomicU32::new(0));
+
+        let start = {
+            let starts = starts.clone();
+            move || {
+                starts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                shutdown.cancel();
+                async { Ok(()) }
+            }
+        };
+        supervisor
+            .supervise("test".to_string(), "push".to_string(), stats.clone(), start)
+            .await;
+
+        assert_eq!(starts.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(stats.totals().restarts, 0);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::config::{RetryConfig, TriggerMode};
+use crate::contracts::IUniswapV2Pair;
+use crate::retry::Backoff;
+use alloy::primitives::Address;
+use alloy::providers::{DynProvider, Provider, ProviderBuilder, WsConnect};
+use alloy::pubsub::RawSubscription;
+use alloy::rpc::types::{Filter, Log};
+use alloy::sol_types::SolEvent;
+use alloy::transports::{RpcError, TransportErrorKind};
+use serde_json::value::RawValue;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Instant, sleep, timeout, timeout_at};
+use tracing::{debug, info, warn};
+
+/// Give up on a WebSocket handshake after this long
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Connection {
+    provider: Option<DynProvider>,
+    /// Bumped on every connect, so a stale drop doesn't close a newer one
+    id: u64,
+    connected_at: Instant,
+    backoff: Backoff,
+    /// No reconnect before this, after a failed or dropped connection
+    retry_at: Option<Instant>,
+}
+
+/// WebSocket connection the pair workers subscribe to chain events on,
+/// shared by all of them and reopened with backoff whenever it drops
+#[derive(Clone)]
+pub struct ChainEvents {
+    url: String,
+    mode: TriggerMode,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl ChainEvents {
+    /// Connects lazily, on the first subscription
+    pub fn new(url: String, mode: TriggerMode, retry: RetryConfig) -> Self {
+        Self {
+            url,
+            mode,
+            connection: Arc::new(Mutex::new(Connection {
+                provider: None,
+                id: 0,
+                connected_at: Instant::now(),
+                backoff: Backoff::new(retry),
+                retry_at: None,
+            })),
+        }
+    }
+
+    /// The open connection and its id, connecting first if none is and the
+    /// backoff allows it
+    async fn provider(&self) -> Option<(u64, DynProvider)> {
+        let mut connection = self.connection.lock().await;
+        if let Some(provider) = &connection.provider {
+            return Some((connection.id, provider.clone()));
+        }
+        if connection
+            .retry_at
+            .is_some_and(|retry_at| Instant::now() < retry_at)
+        {
+            return None;
+        }
+        // alloy makes a single attempt to restore a dropped connection and
+        // its subscriptions, then closes them so they are reopened here
+        let connect =
+            ProviderBuilder::new().connect_ws(WsConnect::new(&self.url).with_max_retries(0));
+        match timeout(CONNECT_TIMEOUT, connect).await {
+            Ok(Ok(provider)) => {
+                info!("Connected to {} for {:?} events", self.url, self.mode);
+                let provider = provider.erased();
+                connection.id += 1;
+                connection.connected_at = Instant::now();
+                connection.retry_at = None;
+                connection.provider = Some(provider.clone());
+                Some((connection.id, provider))
+            }
+            result => {
+                let delay = connection.backoff.failure().delay();
+                connection.retry_at = Some(Instant::now() + delay);
+                let reason = match result {
+                    Ok(Err(e)) => e.to_string(),
+                    _ => format!("no response in {}s", CONNECT_TIMEOUT.as_secs()),
+                };
+                warn!(
+                    "Failed to connect to {}: {}, polling and retrying in {}ms",
+                    self.url,
+                    reason,
+                    delay.as_millis()
+                );
+                None
+            }
+        }
+    }
+
+    /// Drop connection `id` after it closed, unless it was already replaced
+    async fn disconnected(&self, id: u64) {
+        let mut connection = self.connection.lock().await;
+        if connection.id != id || connection.provider.is_none() {
+            return;
+        }
+        let ran_for = connection.connected_at.elapsed();
+        let delay = connection.backoff.stopped(ran_for).delay();
+        connection.retry_at = Some(Instant::now() + delay);
+        connection.provider = None;
+        warn!(
+            "Connection to {} closed, reconnecting in {}ms",
+            self.url,
+            delay.as_millis()
+        );
+    }
+}
+
+/// Whether a subscription item should start a round of `pair`. Sync logs of
+/// other contracts or events are ignored, in case the node filters loosely.
+fn triggers(mode: TriggerMode, pair: Address, item: &RawValue) -> bool {
+    match mode {
+        TriggerMode::NewHeads => true,
+        TriggerMode::Sync => serde_json::from_str::<Log>(item.get()).is_ok_and(|log| {
+            log.address() == pair && log.topic0() == Some(&IUniswapV2Pair::Sync::SIGNATURE_HASH)
+        }),
+    }
+}
+
+/// Decides when a pair worker starts its next round: as soon as the pair may
+/// have changed on chain, or after `fallback` at the latest since the
+/// reference price moves on its own. Without a WebSocket, or while its
+/// subscription is down, it simply waits `fallback`.
+pub struct RoundTrigger {
+    events: Option<ChainEvents>,
+    pair: Address,
+    fallback: Duration,
+    /// The subscription and the id of the connection it is on
+    subscription: Option<(u64, RawSubscription)>,
+}
+
+impl RoundTrigger {
+    pub fn new(events: Option<ChainEvents>, pair: Address, fallback: Duration) -> Self {
+        Self {
+            events,
+            pair,
+            fallback,
+            subscription: None,
+        }
+    }
+
+    /// Wait until the next round is due
+    pub async fn wait(&mut self) {
+        if self.subscription.is_none() {
+            self.subscription = self.subscribe().await;
+        }
+        let (Some(events), Some((connection, subscription))) =
+            (&self.events, &mut self.subscription)
+        else {
+            sleep(self.fallback).await;
+            return;
+        };
+
+        let deadline = Instant::now() + self.fallback;
+        loop {
+            match timeout_at(deadline, subscription.recv()).await {
+                Ok(Ok(item)) if !triggers(events.mode, self.pair, &item) => {
+                    debug!("Ignoring event for pair {}: {}", self.pair, item.get());
+                }
+                Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => {
+                    // Several events may have queued up during the last
+                    // round, one round covers them all
+                    while subscription.try_recv().is_ok() {}
+                    return;
+                }
+                Ok(Err(RecvError::Closed)) => {
+                    warn!(
+                        "Event subscription for pair {} closed, polling until it is restored",
+                        self.pair
+                    );
+                    events.disconnected(*connection).await;
+                    self.subscription = None;
+                    return;
+                }
+                Err(_) => {
+                    debug!("No event for pair {} in {:?}", self.pair, self.fallback);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn subscribe(&self) -> Option<(u64, RawSubscription)> {
+        let events = self.events.as_ref()?;
+        let (connection, provider) = events.provider().await?;
+        let subscription = match events.mode {
+            TriggerMode::NewHeads => provider
+                .subscribe_blocks()
+                .await
+                .map(|subscription| subscription.into_raw()),
+            TriggerMode::Sync => {
+                let filter = Filter::new()
+                    .address(self.pair)
+                    .event_signature(IUniswapV2Pair::Sync::SIGNATURE_HASH);
+                provider
+                    .subscribe_logs(&filter)
+                    .await
+                    .map(|subscription| subscription.into_raw())
+            }
+        };
+        match subscription {
+            Ok(subscription) => Some((connection, subscription)),
+            Err(e) => {
+                warn!(
+                    "Failed to subscribe to events for pair {}, polling instead: {}",
+                    self.pair, e
+                );
+                if matches!(e, RpcError::Transport(TransportErrorKind::BackendGone)) {
+                    events.disconnected(connection).await;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::B256;
+    use futures::{SinkExt, StreamExt};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn log(address: Address, topic0: B256) -> String {
+        json!({
+            "address": address,
+            "topics": [topic0],
+            "data": format!("0x{}", "00".repeat(64)),
+            "blockHash": B256::repeat_byte(0xbb),
+            "blockNumber": "0x10",
+            "transactionHash": B256::repeat_byte(0xcc),
+            "transactionIndex": "0x0",
+            "logIndex": "0x0",
+            "removed": false,
+        })
+        .to_string()
+    }
+
+    fn raw(json: String) -> Box<RawValue> {
+        RawValue::from_string(json).unwrap()
+    }
+
+    #[test]
+    fn only_sync_logs_of_the_pair_trigger() {
+        let pair = Address::repeat_byte(1);
+        let other = Address::repeat_byte(2);
+        let sync = IUniswapV2Pair::Sync::SIGNATURE_HASH;
+        assert!(triggers(TriggerMode::Sync, pair, &raw(log(pair, sync))));
+        assert!(!triggers(TriggerMode::Sync, pair, &raw(log(other, sync))));
+        assert!(!triggers(
+            TriggerMode::Sync,
+            pair,
+            &raw(log(pair, B256::repeat_byte(9)))
+        ));
+        assert!(!triggers(TriggerMode::Sync, pair, &raw("{}".to_string())));
+        // Every new block may have changed the pool
+        assert!(triggers(
+            TriggerMode::NewHeads,
+            pair,
+            &raw("{}".to_string())
+        ));
+    }
+
+    /// Accept one WebSocket connection, acknowledge its subscription and
+    /// send `notifications` on it, then close it. Returns the subscription
+    /// parameters.
+    async fn serve_once(listener: TcpListener, notifications: Vec<String>) -> serde_json::Value {
+        let (stream, _) = listener.accept().await.unwrap();
+        // Refuse alloy's attempt to restore the connection
+        drop(listener);
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        while let Some(message) = ws.next().await {
+            let Message::Text(text) = message.unwrap() else {
+                continue;
+            };
+            let request: serde_json::Value = serde_json::from_str(&text).unwrap();
+            if request["method"] != "eth_subscribe" {
+                continue;
+            }
+            let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": "0x1"});
+            ws.send(Message::text(response.to_string())).await.unwrap();
+            for notification in notifications {
+                let notification = format!(
+                    r#"{{"jsonrpc":"2.0","method":"eth_subscription","params":{{"subscription":"0x1","result":{}}}}}"#,
+                    notification
+                );
+                ws.send(Message::text(notification)).await.unwrap();
+            }
+            // Give the client time to take the notifications before closing
+            sleep(Duration::from_millis(200)).await;
+            ws.close(None).await.unwrap();
+            return request["params"].clone();
+        }
+        panic!("no subscription requested");
+    }
+
+    #[tokio::test]
+    async fn polls_while_the_stream_is_closed_then_resubscribes() {
+        let pair = Address::repeat_byte(1);
+        let sync = IUniswapV2Pair::Sync::SIGNATURE_HASH;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_once(listener, vec![log(pair, sync)]));
+        let retry = RetryConfig {
+            initial_delay_ms: 200,
+            jitter: 0.0,
+            ..RetryConfig::default()
+        };
+        let events = ChainEvents::new(format!("ws://{}", address), TriggerMode::Sync, retry);
+        let fallback = Duration::from_secs(1);
+        let mut trigger = RoundTrigger::new(Some(events), pair, fallback);
+
+        // The pair's Sync log starts the round early
+        let started = Instant::now();
+        trigger.wait().await;
+        assert!(started.elapsed() < fallback);
+        let params = server.await.unwrap();
+        assert_eq!(params[0], "logs");
+        assert_eq!(params[1]["address"], json!(pair));
+        assert_eq!(params[1]["topics"][0], json!(sync));
+
+        // The server went away, so the trigger notices the closed stream and
+        // waits out the fallback rather than reconnecting before the backoff
+        trigger.wait().await;
+        assert!(trigger.subscription.is_none());
+        let started = Instant::now();
+        trigger.wait().await;
+        assert!(started.elapsed() >= fallback);
+        assert!(trigger.subscription.is_none());
+
+        // Once the node is back the next round resubscribes
+        let listener = TcpListener::bind(address).await.unwrap();
+        let server = tokio::spawn(serve_once(listener, vec![log(pair, sync)]));
+        let started = Instant::now();
+        trigger.wait().await;
+        assert!(started.elapsed() < fallback);
+        assert_eq!(server.await.unwrap()[1]["address"], json!(pair));
+    }
+}
@@ -0,0 +1,517 @@
+use crate::config::RetryConfig;
+use crate::contracts::{IERC20, IUniswapV2Pair};
+use crate::pricing::SwapDirection;
+use crate::retry::Backoff;
+use crate::submitter::{ReplaceError, SubmittedTx, TxSubmitter};
+use alloy::contract;
+use alloy::primitives::{Address, TxHash, U256, hex};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionReceipt;
+use alloy::sol_types::{Revert, SolError};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// How often pending transactions are polled for a receipt
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Ways a submitted transaction can fail to produce a successful receipt
+#[derive(Debug, Error)]
+pub enum TxError {
+    #[error("Transaction {tx_hash} reverted in block {block}")]
+    Reverted { tx_hash: TxHash, block: u64 },
+    #[error("Transaction {0} was dropped from the mempool")]
+    Dropped(TxHash),
+    #[error("Transaction {tx_hash} not mined after {}s", .waited.as_secs())]
+    Timeout { tx_hash: TxHash, waited: Duration },
+    #[error("Transaction {tx_hash} still pending after {blocks} blocks")]
+    Stuck { tx_hash: TxHash, blocks: u64 },
+    #[error("Transaction with nonce {nonce} was cancelled by {tx_hash}")]
+    Cancelled { nonce: u64, tx_hash: TxHash },
+    #[error("Transaction {tx_hash} mined in block {block} without swapping anything")]
+    NoSwap { tx_hash: TxHash, block: u64 },
+}
+
+/// Why a simulated swap reverted, decoded from the router's revert reason
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RevertReason {
+    #[error("output below amountOutMin")]
+    InsufficientOutputAmount,
+    #[error("deadline expired")]
+    Expired,
+    #[error("insufficient liquidity")]
+    InsufficientLiquidity,
+    #[error("token transfer failed")]
+    TransferFailed,
+    #[error("{0}")]
+    Other(String),
+}
+
+impl RevertReason {
+    /// Classify a revert reason string such as
+    /// "UniswapV2Router: INSUFFICIENT_OUTPUT_AMOUNT"
+    pub fn from_reason(reason: &str) -> Self {
+        if reason.contains("INSUFFICIENT_OUTPUT_AMOUNT") {
+            RevertReason::InsufficientOutputAmount
+        } else if reason.contains("EXPIRED") {
+            RevertReason::Expired
+        } else if reason.contains("INSUFFICIENT_LIQUIDITY") {
+            RevertReason::InsufficientLiquidity
+        } else if reason.contains("TRANSFER_FROM_FAILED") || reason.contains("TRANSFER_FAILED") {
+            RevertReason::TransferFailed
+        } else {
+            RevertReason::Other(reason.to_string())
+        }
+    }
+
+    /// Decode ABI-encoded `Error(string)` revert data
+    pub fn from_revert_data(data: &[u8]) -> Self {
+        match Revert::abi_decode(data) {
+            Ok(revert) => Self::from_reason(&revert.reason),
+            Err(_) if data.is_empty() => RevertReason::Other("no reason given".to_string()),
+            Err(_) => RevertReason::Other(format!(
+                "undecodable revert data {}",
+                hex::encode_prefixed(data)
+            )),
+        }
+    }
+}
+
+/// Result of simulating a transaction with `eth_call` before sending it
+#[derive(Debug, Error)]
+pub enum SimulationError {
+    #[error("Simulation reverted: {0}")]
+    Reverted(RevertReason),
+    #[error("Simulation failed: {0}")]
+    Rpc(contract::Error),
+}
+
+impl From<contract::Error> for SimulationError {
+    fn from(error: contract::Error) -> Self {
+        if let Some(data) = error.as_revert_data() {
+            return SimulationError::Reverted(RevertReason::from_revert_data(&data));
+        }
+        // Some nodes only report the reason in the error message
+        let message = error.to_string();
+        match message.split_once("execution reverted") {
+            Some((_, reason)) => SimulationError::Reverted(RevertReason::from_reason(
+                reason.trim_start_matches(':').trim(),
+            )),
+            None => SimulationError::Rpc(error),
+        }
+    }
+}
+
+/// How to handle transactions that stay in the mempool
+#[derive(Debug, Clone)]
+pub struct ReplacementPolicy {
+    /// Blocks without inclusion before a transaction counts as stuck
+    pub stuck_after_blocks: u64,
+    /// Minimum fee increase of each replacement, in percent
+    pub bump_percent: u64,
+    /// Replacements to try before giving up on the nonce
+    pub max_replacements: u32,
+    /// Overall time to wait for any version of the transaction
+    pub receipt_timeout: Duration,
+    /// Backoff between receipt polls while the node keeps failing
+    pub retry: RetryConfig,
+}
+
+/// Confirmed result of a swap, decoded from its receipt logs
+#[derive(Debug, Clone)]
+pub struct SwapOutcome {
+    pub tx_hash: TxHash,
+    pub block: u64,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub gas_used: u64,
+    pub effective_gas_price: u128,
+}
+
+impl SwapOutcome {
+    /// Total fee paid for the transaction, in wei
+    pub fn gas_cost(&self) -> U256 {
+        U256::from(self.gas_used) * U256::from(self.effective_gas_price)
+    }
+}
+
+/// Poll until one of `tx_hashes` is mined or `timeout` elapses. All hashes
+/// are versions of the same nonce, so at most one of them can be included.
+/// With `stuck_after_blocks` set, gives up with [`TxError::Stuck`] once that
+/// many blocks pass without inclusion. Transactions the node no longer knows
+/// about after the timeout are reported as dropped. Polls back off according
+/// to `retry` while the node returns errors.
+pub async fn wait_for_receipt<P: Provider>(
+    provider: &P,
+    tx_hashes: &[TxHash],
+    timeout: Duration,
+    stuck_after_blocks: Option<u64>,
+    retry: &RetryConfig,
+) -> Result<TransactionReceipt, TxError> {
+    let latest_hash = *tx_hashes.last().expect("no transaction to wait for");
+    let started = Instant::now();
+    let mut first_block = None;
+    let mut backoff = Backoff::new(retry.clone());
+    loop {
+        let mut failed = false;
+        for &tx_hash in tx_hashes {
+            match provider.get_transaction_receipt(tx_hash).await {
+                Ok(Some(receipt)) => {
+                    if !receipt.status() {
+                        return Err(TxError::Reverted {
+                            tx_hash,
+                            block: receipt.block_number.unwrap_or_default(),
+                        });
+                    }
+                    return Ok(receipt);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Failed to fetch receipt for {}: {}", tx_hash, e);
+                    failed = true;
+                }
+            }
+        }
+
+        if let Some(stuck_after_blocks) = stuck_after_blocks {
+            match provider.get_block_number().await {
+                Ok(block) => {
+                    let first = *first_block.get_or_insert(block);
+                    let blocks = block.saturating_sub(first);
+                    if blocks >= stuck_after_blocks {
+                        return Err(TxError::Stuck {
+                            tx_hash: latest_hash,
+                            blocks,
+                        });
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to fetch block number: {}", e);
+                    failed = true;
+                }
+            }
+        }
+
+        if started.elapsed() >= timeout {
+            for &tx_hash in tx_hashes {
+                if !matches!(provider.get_transaction_by_hash(tx_hash).await, Ok(None)) {
+                    return Err(TxError::Timeout {
+                        tx_hash: latest_hash,
+                        waited: started.elapsed(),
+                    });
+                }
+            }
+            return Err(TxError::Dropped(latest_hash));
+        }
+        let delay = if failed {
+            backoff.failure().delay().max(RECEIPT_POLL_INTERVAL)
+        } else {
+            backoff.success();
+            RECEIPT_POLL_INTERVAL
+        };
+        // Wake up for the dropped check once the timeout is reached
+        let until_timeout = timeout.saturating_sub(started.elapsed());
+        sleep(delay.min(until_timeout.max(RECEIPT_POLL_INTERVAL))).await;
+    }
+}
+
+/// Wait for a submitted transaction, replacing it with higher fees whenever it
+/// gets stuck. `still_needed` is asked before every replacement; once it says
+/// the transaction is no longer wanted or would no longer succeed, its nonce
+/// is freed with a cancellation instead.
+pub async fn confirm<P, F, Fut>(
+    provider: &P,
+    submitter: &TxSubmitter,
+    submitted: SubmittedTx,
+    policy: &ReplacementPolicy,
+    still_needed: F,
+) -> Result<TransactionReceipt, TxError>
+where
+    P: Provider,
+    F: Fn() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let started = Instant::now();
+    let nonce = submitted.nonce;
+    let mut tx_hashes = vec![submitted.tx_hash];
+    let mut latest = submitted;
+    // Hashes of the zero-value transfers that replaced the original
+    let mut cancellations = Vec::new();
+    let mut replacements = 0;
+    // Cleared once fees can't be raised any further
+    let mut stuck_after_blocks = Some(policy.stuck_after_blocks);
+    loop {
+        let remaining = policy.receipt_timeout.saturating_sub(started.elapsed());
+        let stuck = match wait_for_receipt(
+            provider,
+            &tx_hashes,
+            remaining,
+            stuck_after_blocks,
+            &policy.retry,
+        )
+        .await
+        {
+            Ok(receipt) if cancellations.contains(&receipt.transaction_hash) => {
+                return Err(TxError::Cancelled {
+                    nonce,
+                    tx_hash: receipt.transaction_hash,
+                });
+            }
+            Err(stuck @ TxError::Stuck { .. }) => stuck,
+            result => return result,
+        };
+        if replacements >= policy.max_replacements {
+            return Err(stuck);
+        }
+        replacements += 1;
+
+        // A cancellation is only ever replaced by a higher-fee cancellation
+        let cancel = !cancellations.is_empty() || !still_needed().await;
+        let resent = if cancel {
+            submitter.cancel(&latest, policy.bump_percent).await
+        } else {
+            submitter.replace(&latest, policy.bump_percent).await
+        };
+        match resent {
+            Ok(resent) if resent.tx_hash == latest.tx_hash => {
+                info!("{}, identical transaction already pending", stuck)
+            }
+            Ok(resent) => {
+                let action = if cancel { "cancelled" } else { "replaced" };
+                info!("{}, {} by {}", stuck, action, resent.tx_hash);
+                if cancel {
+                    cancellations.push(resent.tx_hash);
+                }
+                tx_hashes.push(resent.tx_hash);
+                latest = resent;
+            }
+            Err(e) => match e.downcast_ref::<ReplaceError>() {
+                Some(ReplaceError::FeeCapReached { .. }) => {
+                    warn!("{}, {}, waiting for it until the timeout", stuck, e);
+                    stuck_after_blocks = None;
+                }
+                Some(ReplaceError::Underpriced { .. }) => warn!("{}, not replaced: {}", stuck, e),
+                // Usually means one of the earlier versions was just mined
+                None => warn!("Failed to replace transaction with nonce {}: {}", nonce, e),
+            },
+        }
+    }
+}
+
+/// Extract the amounts actually swapped from a mined receipt.
+///
+/// Prefers the pair's `Swap` event; falls back to the `Transfer` events of
+/// the input and output tokens to and from `wallet`. Returns `None` if the
+/// receipt has neither.
+pub fn decode_swap(
+    receipt: &TransactionReceipt,
+    pair_address: Address,
+    direction: SwapDirection,
+    input_token: Address,
+    output_token: Address,
+    wallet: Address,
+) -> Option<SwapOutcome> {
+    let logs = receipt.inner.logs();
+
+    let swap = logs
+        .iter()
+        .filter(|log| log.address() == pair_address)
+        .find_map(|log| log.log_decode::<IUniswapV2Pair::Swap>().ok())
+        .map(|log| log.inner.data);
+
+    let (amount_in, amount_out) = match swap {
+        Some(swap) => match direction {
+            SwapDirection::Token0ToToken1 => (swap.amount0In, swap.amount1Out),
+            SwapDirection::Token1ToToken0 => (swap.amount1In, swap.amount0Out),
+        },
+        None => {
+            let transfers: Vec<_> = logs
+                .iter()
+                .filter_map(|log| {
+                    log.log_decode::<IERC20::Transfer>()
+                        .ok()
+                        .map(|decoded| (log.address(), decoded.inner.data))
+                })
+                .collect();
+            let sent: Vec<U256> = transfers
+                .iter()
+                .filter(|(token, t)| *token == input_token && t.from == wallet)
+                .map(|(_, t)| t.value)
+                .collect();
+            let received: Vec<U256> = transfers
+                .iter()
+                .filter(|(token, t)| *token == output_token && t.to == wallet)
+                .map(|(_, t)| t.value)
+                .collect();
+            if sent.is_empty() || received.is_empty() {
+                return None;
+            }
+            (sent.into_iter().sum(), received.into_iter().sum())
+        }
+    };
+
+    Some(SwapOutcome {
+        tx_hash: receipt.transaction_hash,
+        block: receipt.block_number.unwrap_or_default(),
+        amount_in,
+        amount_out,
+        gas_used: receipt.gas_used,
+        effective_gas_price: receipt.effective_gas_price,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+    use alloy::sol_types::SolEvent;
+    use serde_json::json;
+
+    const PAIR: Address = address!("0x9d5868afd7e4ddaec6cd6d45de7c268d912bf8a6");
+    const ROUTER: Address = address!("0xa1cf48c109f8b5eee38b406591fe27f11f685a1f");
+    const WALLET: Address = address!("0x5b38da6a701c568545dcfcb03fcb875f56beddc4");
+    const TOKEN0: Address = address!("0x231dfcfd9def41f86f5b7ab77ef0946c0cda3b1b");
+    const TOKEN1: Address = address!("0x3512b762158ff2cacb0551215618ec2df303fdd9");
+    const SWAP_TOPIC: &str = "0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822";
+    const TRANSFER_TOPIC: &str =
+        "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+    fn topic(address: Address) -> String {
+        format!("0x{:0>64}", hex::encode(address))
+    }
+
+    fn word(value: u64) -> String {
+        format!("{:064x}", value)
+    }
+
+    fn receipt(logs: Vec<serde_json::Value>) -> TransactionReceipt {
+        let logs: Vec<_> = logs
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut log)| {
+                log["blockNumber"] = json!("0x2a");
+                log["transactionIndex"] = json!("0x0");
+                log["logIndex"] = json!(format!("0x{:x}", index));
+                log["removed"] = json!(false);
+                log
+            })
+            .collect();
+        serde_json::from_value(json!({
+            "type": "0x2",
+            "status": "0x1",
+            "transactionHash": format!("0x{}", "ab".repeat(32)),
+            "transactionIndex": "0x0",
+            "blockHash": format!("0x{}", "cd".repeat(32)),
+            "blockNumber": "0x2a",
+            "from": WALLET,
+            "to": ROUTER,
+            "contractAddress": null,
+            "cumulativeGasUsed": "0x1d4c0",
+            "gasUsed": "0x1d4c0",
+            "effectiveGasPrice": "0x3b9aca00",
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "logs": logs,
+        }))
+        .unwrap()
+    }
+
+    /// Swap event as emitted by a UniswapV2Pair: sender and recipient
+    /// indexed, then amount0In, amount1In, amount0Out and amount1Out
+    fn swap_log(amounts: [u64; 4]) -> serde_json::Value {
+        json!({
+            "address": PAIR,
+            "topics": [SWAP_TOPIC, topic(ROUTER), topic(WALLET)],
+            "data": format!("0x{}", amounts.map(word).concat()),
+        })
+    }
+
+    fn transfer_log(token: Address, from: Address, to: Address, value: u64) -> serde_json::Value {
+        json!({
+            "address": token,
+            "topics": [TRANSFER_TOPIC, topic(from), topic(to)],
+            "data": format!("0x{}", word(value)),
+        })
+    }
+
+    fn decode(receipt: &TransactionReceipt, direction: SwapDirection) -> Option<SwapOutcome> {
+        let (input, output) = match direction {
+            SwapDirection::Token0ToToken1 => (TOKEN0, TOKEN1),
+            SwapDirection::Token1ToToken0 => (TOKEN1, TOKEN0),
+        };
+        decode_swap(receipt, PAIR, direction, input, output, WALLET)
+    }
+
+    #[test]
+    fn event_topics_match_contracts() {
+        assert_eq!(IUniswapV2Pair::Swap::SIGNATURE_HASH.to_string(), SWAP_TOPIC);
+        assert_eq!(IERC20::Transfer::SIGNATURE_HASH.to_string(), TRANSFER_TOPIC);
+    }
+
+    #[test]
+    fn decodes_swap_event_in_both_directions() {
+        let sell0 = receipt(vec![
+            transfer_log(TOKEN0, WALLET, PAIR, 5_000),
+            transfer_log(TOKEN1, PAIR, WALLET, 12_345_678),
+            swap_log([5_000, 0, 0, 12_345_678]),
+        ]);
+        let outcome = decode(&sell0, SwapDirection::Token0ToToken1).unwrap();
+        assert_eq!(outcome.amount_in, U256::from(5_000));
+        assert_eq!(outcome.amount_out, U256::from(12_345_678));
+        assert_eq!(outcome.block, 42);
+        assert_eq!(outcome.gas_cost(), U256::from(120_000u64 * 1_000_000_000));
+
+        let sell1 = receipt(vec![swap_log([0, 7_000_000, 2_718, 0])]);
+        let outcome = decode(&sell1, SwapDirection::Token1ToToken0).unwrap();
+        assert_eq!(outcome.amount_in, U256::from(7_000_000));
+        assert_eq!(outcome.amount_out, U256::from(2_718));
+    }
+
+    #[test]
+    fn falls_back_to_wallet_transfers() {
+        // Swap event from another pair, e.g. a routed hop
+        let mut other_swap = swap_log([1, 0, 0, 1]);
+        other_swap["address"] = json!(TOKEN0);
+        let routed = receipt(vec![
+            transfer_log(TOKEN1, WALLET, PAIR, 900),
+            transfer_log(TOKEN0, PAIR, WALLET, 3),
+            other_swap,
+        ]);
+        let outcome = decode(&routed, SwapDirection::Token1ToToken0).unwrap();
+        assert_eq!(outcome.amount_in, U256::from(900));
+        assert_eq!(outcome.amount_out, U256::from(3));
+    }
+
+    #[test]
+    fn receipt_without_swap_is_not_a_zero_swap() {
+        assert!(decode(&receipt(Vec::new()), SwapDirection::Token0ToToken1).is_none());
+        // Nor does a transfer the wallet took no part in
+        let unrelated = receipt(vec![transfer_log(TOKEN1, PAIR, ROUTER, 10)]);
+        assert!(decode(&unrelated, SwapDirection::Token0ToToken1).is_none());
+    }
+
+    #[test]
+    fn decodes_router_revert_reasons() {
+        let data = Revert::from("UniswapV2Router: INSUFFICIENT_OUTPUT_AMOUNT").abi_encode();
+        assert_eq!(
+            RevertReason::from_revert_data(&data),
+            RevertReason::InsufficientOutputAmount
+        );
+        assert_eq!(
+            RevertReason::from_reason("UniswapV2Router: EXPIRED"),
+            RevertReason::Expired
+        );
+        assert_eq!(
+            RevertReason::from_reason("TransferHelper: TRANSFER_FROM_FAILED"),
+            RevertReason::TransferFailed
+        );
+        assert_eq!(
+            RevertReason::from_reason("Pausable: paused"),
+            RevertReason::Other("Pausable: paused".to_string())
+        );
+        assert_eq!(
+            RevertReason::from_revert_data(&[]),
+            RevertReason::Other("no reason given".to_string())
+        );
+    }
+}
@@ -0,0 +1,1012 @@
+use crate::breaker::{DivergenceBreaker, Resumed, Verdict};
+use crate::config::{PoolConfig, RetryConfig};
+use crate::contracts::IERC20::{self, IERC20Instance};
+use crate::contracts::IUniswapV2Pair::{self, IUniswapV2PairInstance};
+use crate::contracts::IUniswapV2Router::IUniswapV2RouterInstance;
+use crate::error::BotError;
+use crate::gas::{GasEstimate, GasPriceTooHighError, GasStrategy};
+use crate::journal::{Decision, Journal, RoundRecord};
+use crate::kuma::{KumaPushClient, KumaStatus};
+use crate::limits::TradeLimits;
+use crate::oracle::OracleError;
+use crate::paper::{PaperAccount, PaperWallet};
+use crate::price_source::{ReferencePrice, StalePriceError};
+use crate::pricing::{self, Ratio, Rebalance, SwapDirection};
+use crate::retry::{Backoff, Retry};
+use crate::stats::PairStats;
+use crate::submitter::TxSubmitter;
+use crate::trigger::{ChainEvents, RoundTrigger};
+use crate::tx::{ReplacementPolicy, SimulationError, SwapOutcome, TxError, confirm, decode_swap};
+use alloy::eips::{BlockId, BlockNumberOrTag};
+use alloy::primitives::{Address, U256};
+use alloy::providers::Provider;
+use anyhow::anyhow;
+use rust_decimal::{Decimal, dec};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Pause between rounds that ended normally, or the longest one when rounds
+/// are driven by chain events
+const ROUND_INTERVAL: Duration = Duration::from_secs(9);
+
+/// A stuck swap this close to its deadline is cancelled rather than
+/// replaced, as the replacement would likely expire before being mined
+const REPLACEMENT_DEADLINE_MARGIN: Duration = Duration::from_secs(30);
+
+/// Native balance below which the wallet needs topping up (0.1 coin)
+const MIN_GAS_BALANCE: u128 = 100_000_000_000_000_000;
+
+/// Gas a UniswapV2 swap is assumed to use in dry runs whose input token's
+/// storage couldn't be overridden, unless a gas limit is configured
+const DRY_RUN_SWAP_GAS: u64 = 150_000;
+
+/// Quote `source`, rejecting prices that are stale, disputed or not positive
+/// so the pair reports Down rather than trading on them
+async fn checked_quote(
+    what: &'static str,
+    source: &ReferencePrice,
+    max_age: Duration,
+) -> Result<Decimal, BotError> {
+    match source
+        .quote()
+        .await
+        .and_then(|quote| quote.check_age(max_age))
+    {
+        Ok(quote) if quote.price <= Decimal::ZERO => Err(BotError::PriceRejected(anyhow!(
+            "{} price {} from {} is not positive",
+            what,
+            quote.price,
+            source.describe()
+        ))),
+        Ok(quote) => Ok(quote.price),
+        Err(e) if e.is::<OracleError>() || e.is::<StalePriceError>() => {
+            Err(BotError::PriceRejected(e))
+        }
+        Err(e) => Err(BotError::PriceUnavailable {
+            what,
+            source_name: source.describe(),
+            source: e,
+        }),
+    }
+}
+
+/// Largest input `limits` allow right now, failing once the daily volume
+/// cap is used up and `None` if they otherwise round down to nothing, e.g.
+/// `max_balance_fraction` of a dust balance
+fn capped_input(
+    limits: &TradeLimits,
+    balance: U256,
+    volume: U256,
+    frees_up_in: Duration,
+) -> Result<Option<U256>, BotError> {
+    let max_input = limits.max_input(balance, volume);
+    if !max_input.is_zero() {
+        return Ok(Some(max_input));
+    }
+    if limits.max_daily_volume.is_some_and(|cap| volume >= cap) {
+        return Err(BotError::VolumeCapReached {
+            resets_in: frees_up_in,
+        });
+    }
+    Ok(None)
+}
+
+/// Everything the pair workers share
+#[derive(Clone)]
+pub struct WorkerContext<P> {
+    pub provider: P,
+    pub wallet: Address,
+    pub router: IUniswapV2RouterInstance<P>,
+    pub submitter: TxSubmitter,
+    pub gas: GasStrategy,
+    pub kuma: Arc<KumaPushClient>,
+    pub swap_deadline_secs: u64,
+    pub replacement_policy: ReplacementPolicy,
+    pub retry: RetryConfig,
+    /// Cancelled when the bot is asked to stop
+    pub shutdown: CancellationToken,
+    /// Simulate trades and book them on `paper` without ever signing or
+    /// sending a transaction
+    pub dry_run: bool,
+    /// Balances every pair trades against in a dry run
+    pub paper: PaperWallet,
+    /// Chain event subscriptions driving the rounds, if a WebSocket RPC is set
+    pub events: Option<ChainEvents>,
+    /// Bumped every time halted pairs are resumed by hand
+    pub resume: watch::Receiver<u64>,
+    /// Wrapped native gas token, which prices gas on pairs that trade it
+    pub wrapped_native: Option<Address>,
+    pub native_decimals: u8,
+    /// Where every round is recorded, if enabled
+    pub journal: Option<Journal>,
+}
+
+struct Token<P> {
+    contract: IERC20Instance<P>,
+    decimals: u8,
+    min_balance: U256,
+    limits: TradeLimits,
+}
+
+/// How a round of the pair loop ended
+enum Round {
+    /// The pool is close enough to the reference price
+    InRange,
+    /// A rebalance was possible but not worth its gas
+    Unprofitable,
+    /// The trade limits round the swap input down to zero
+    NothingToSell,
+    Swapped(SwapOutcome),
+    /// Dry runs only: the swap was booked on paper
+    Simulated {
+        amount_in: U256,
+        expected_out: U256,
+        gas_cost: U256,
+    },
+}
+
+/// Keeps one pool pegged to its reference price
+pub struct PairWorker<P> {
+    ctx: WorkerContext<P>,
+    pair: PoolConfig,
+    reference: ReferencePrice,
+    native_price: Option<ReferencePrice>,
+    max_price_age: Duration,
+    breaker: Option<DivergenceBreaker>,
+    stats: PairStats,
+    pair_contract: IUniswapV2PairInstance<P>,
+    token0: Token<P>,
+    token1: Token<P>,
+}
+
+impl<P: Provider + Clone + 'static> PairWorker<P> {
+    /// Load the pair's token metadata
+    pub async fn connect(
+        ctx: WorkerContext<P>,
+        pair: PoolConfig,
+        reference: ReferencePrice,
+        native_price: Option<ReferencePrice>,
+        stats: PairStats,
+    ) -> Result<Self, BotError> {
+        let token0 = IERC20::new(pair.token0, ctx.provider.clone());
+        let decimals0 = token0
+            .decimals()
+            .call()
+            .await
+            .map_err(BotError::rpc("fetch token0 decimals"))?;
+        let token1 = IERC20::new(pair.token1, ctx.provider.clone());
+        let decimals1 = token1
+            .decimals()
+            .call()
+            .await
+            .map_err(BotError::rpc("fetch token1 decimals"))?;
+
+        let limits =
+            |decimals, max_trade_size: Option<Decimal>, max_daily_volume: Option<Decimal>| {
+                let to_raw = |amount: Decimal| {
+                    pricing::from_decimal(amount, decimals)
+                        .ok_or_else(|| BotError::Unrepresentable(format!("Trade limit {}", amount)))
+                };
+                Ok::<_, BotError>(TradeLimits {
+                    max_trade_size: max_trade_size.map(to_raw).transpose()?,
+                    max_balance_fraction: pair.max_balance_fraction,
+                    max_daily_volume: max_daily_volume.map(to_raw).transpose()?,
+                })
+            };
+        let limits0 = limits(decimals0, pair.max_trade_size0, pair.max_daily_volume0)?;
+        let limits1 = limits(decimals1, pair.max_trade_size1, pair.max_daily_volume1)?;
+
+        // The pair's paper trades outlive worker restarts
+        if ctx.dry_run && stats.totals().paper.is_none() {
+            stats.record(|totals| totals.paper = Some(PaperAccount::default()));
+        }
+
+        Ok(Self {
+            pair_contract: IUniswapV2Pair::new(pair.pair_address, ctx.provider.clone()),
+            token0: Token {
+                contract: token0,
+                decimals: decimals0,
+                min_balance: U256::from(pair.min_balance0),
+                limits: limits0,
+            },
+            token1: Token {
+                contract: token1,
+                decimals: decimals1,
+                min_balance: U256::from(pair.min_balance1),
+                limits: limits1,
+            },
+            max_price_age: Duration::from_secs(pair.max_price_age_secs),
+            breaker: pair
+                .halt_deviation_bps
+                .map(|halt_deviation_bps| DivergenceBreaker {
+                    halt_deviation_bps: Decimal::from(halt_deviation_bps),
+                    cooldown: Duration::from_secs(pair.halt_cooldown_secs),
+                }),
+            ctx,
+            pair,
+            reference,
+            native_price,
+            stats,
+        })
+    }
+
+    /// Run the pair loop until shutdown. A round that already sent a
+    /// transaction is finished first, so nothing is left half-submitted.
+    pub async fn run(self) {
+        let mut backoff = Backoff::new(self.ctx.retry.clone());
+        let mut trigger = RoundTrigger::new(
+            self.ctx.events.clone(),
+            self.pair.pair_address,
+            ROUND_INTERVAL,
+        );
+        while !self.ctx.shutdown.is_cancelled() {
+            let mut record = RoundRecord::new(&self.pair.name);
+            let round = self.round(&mut record).await;
+            self.journal(record, &round);
+            if !matches!(&round, Err(e) if e.retry_after().is_none()) {
+                backoff.success();
+            }
+            match round {
+                Ok(Round::InRange) => self.report(KumaStatus::Up, "Pair is up").await,
+                Ok(Round::Unprofitable) | Ok(Round::NothingToSell) => {}
+                Ok(Round::Swapped(outcome)) => {
+                    self.stats.record(|totals| {
+                        totals.swaps += 1;
+                        totals.gas_cost += outcome.gas_cost();
+                    });
+                    info!(
+                        "{}: swap {} confirmed in block {}: in {}, out {}, gas {} ({} wei)",
+                        self.pair.name,
+                        outcome.tx_hash,
+                        outcome.block,
+                        outcome.amount_in,
+                        outcome.amount_out,
+                        outcome.gas_used,
+                        outcome.gas_cost()
+                    );
+                    let msg = format!(
+                        "Swap {} confirmed: in {}, out {}",
+                        outcome.tx_hash, outcome.amount_in, outcome.amount_out
+                    );
+                    self.report(KumaStatus::Up, &msg).await;
+                }
+                Ok(Round::Simulated {
+                    amount_in,
+                    expected_out,
+                    gas_cost,
+                }) => {
+                    let totals = self.stats.totals();
+                    info!(
+                        "{}: dry run, would swap in {}, out {}, gas {} wei ({} swaps simulated, paper PnL {} token1)",
+                        self.pair.name,
+                        amount_in,
+                        expected_out,
+                        gas_cost,
+                        totals.simulated_swaps,
+                        totals
+                            .paper_pnl
+                            .map_or("unknown".to_string(), |pnl| pnl.to_string())
+                    );
+                    let msg = format!("Dry run: would swap in {}, out {}", amount_in, expected_out);
+                    self.report(KumaStatus::Up, &msg).await;
+                }
+                Err(BotError::ShuttingDown) => break,
+                Err(e) => {
+                    if let BotError::SwapFailed(_) = e {
+                        self.stats.record(|totals| totals.failed_swaps += 1);
+                    }
+                    error!("{}: {}", self.pair.name, e);
+                    if e.reports_down() {
+                        self.report(KumaStatus::Down, &e.to_string()).await;
+                    }
+                    let delay = match e.retry_after() {
+                        Some(delay) => delay,
+                        None => match backoff.failure() {
+                            Retry::After(delay) => delay,
+                            Retry::CircuitOpen(delay) => {
+                                let msg = format!(
+                                    "Pausing for {}s after repeated failures: {}",
+                                    delay.as_secs(),
+                                    e
+                                );
+                                error!("{}: {}", self.pair.name, msg);
+                                self.report(KumaStatus::Down, &msg).await;
+                                delay
+                            }
+                        },
+                    };
+                    self.pause(delay).await;
+                    continue;
+                }
+            }
+            tokio::select! {
+                _ = trigger.wait() => {}
+                _ = self.ctx.shutdown.cancelled() => {}
+            }
+        }
+        info!("{}: worker stopped", self.pair.name);
+    }
+
+    /// Record how the round ended in the journal, if one is configured
+    fn journal(&self, mut record: RoundRecord, round: &Result<Round, BotError>) {
+        let Some(journal) = &self.ctx.journal else {
+            return;
+        };
+        record.decision = match round {
+            Ok(Round::InRange) => Decision::InRange,
+            Ok(Round::Unprofitable) => Decision::Unprofitable,
+            Ok(Round::NothingToSell) => Decision::NothingToSell,
+            Ok(Round::Swapped(outcome)) => {
+                record.outcome = Some(outcome.clone());
+                Decision::Swapped
+            }
+            Ok(Round::Simulated { .. }) => Decision::Simulated,
+            Err(BotError::ShuttingDown) => return,
+            Err(e) => {
+                record.error = Some(e.to_string());
+                Decision::Failed
+            }
+        };
+        journal.record(record);
+    }
+
+    /// Sleep, waking early on shutdown
+    async fn pause(&self, delay: Duration) {
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = self.ctx.shutdown.cancelled() => {}
+        }
+    }
+
+    /// Refuse to send new transactions once shutdown has begun
+    fn ensure_running(&self) -> Result<(), BotError> {
+        if self.ctx.shutdown.is_cancelled() {
+            return Err(BotError::ShuttingDown);
+        }
+        Ok(())
+    }
+
+    async fn report(&self, status: KumaStatus, msg: &str) {
+        if let Err(e) = self
+            .ctx
+            .kuma
+            .push(&self.pair.kuma_push_id, status, Some(msg))
+            .await
+        {
+            error!("Failed to send status update to Kuma push: {}", e);
+        }
+    }
+
+    async fn reserves(&self) -> Result<(U256, U256), BotError> {
+        let reserves = self
+            .pair_contract
+            .getReserves()
+            .call()
+            .await
+            .map_err(BotError::rpc("fetch reserves"))?;
+        Ok((U256::from(reserves.reserve0), U256::from(reserves.reserve1)))
+    }
+
+    /// Price of token0 in token1 from the pool reserves
+    fn pool_price(&self, reserve0: U256, reserve1: U256) -> Option<Decimal> {
+        // price = (r1 * 10^d0) / (r0 * 10^d1)
+        pricing::pool_price(
+            reserve0,
+            reserve1,
+            self.token0.decimals,
+            self.token1.decimals,
+        )
+    }
+
+    /// Price of the native gas token in token0, from `native_price_source` or,
+    /// if one of the pair's tokens is the wrapped native token, the pair's own
+    /// reference price
+    async fn native_price0(&self, reference_price: Decimal) -> Result<Decimal, BotError> {
+        if let Some(native_price) = &self.native_price {
+            return self.quote("native token", native_price).await;
+        }
+        match self.ctx.wrapped_native {
+            Some(wrapped) if wrapped == self.pair.token0 => Ok(Decimal::ONE),
+            Some(wrapped) if wrapped == self.pair.token1 => {
+                Decimal::ONE.checked_div(reference_price).ok_or_else(|| {
+                    BotError::Unrepresentable(format!("Native price at {}", reference_price))
+                })
+            }
+            // Config validation rejects pairs without either
+            _ => Err(BotError::Unrepresentable(
+                "Gas without a native token price".to_string(),
+            )),
+        }
+    }
+
+    async fn quote(
+        &self,
+        what: &'static str,
+        source: &ReferencePrice,
+    ) -> Result<Decimal, BotError> {
+        checked_quote(what, source, self.max_price_age).await
+    }
+
+    async fn round(&self, record: &mut RoundRecord) -> Result<Round, BotError> {
+        let (reserve0, reserve1) = self.reserves().await?;
+        record.reserves = Some((reserve0, reserve1));
+        let pool_price = self
+            .pool_price(reserve0, reserve1)
+            .ok_or(BotError::NoLiquidity)?;
+        record.pool_price = Some(pool_price);
+        let reference_price = self.quote("reference", &self.reference).await?;
+        record.reference_price = Some(reference_price);
+
+        let deviation = pricing::deviation_bps(pool_price, reference_price).ok_or_else(|| {
+            BotError::Unrepresentable(format!(
+                "Deviation from reference price {}",
+                reference_price
+            ))
+        })?;
+        record.deviation_bps = Some(deviation);
+        self.check_breaker(pool_price, reference_price, deviation)
+            .await?;
+        if deviation < Decimal::from(self.pair.deviation_threshold_bps) {
+            debug!(
+                "{}: pool price {} within {} bps of reference {}, skipping",
+                self.pair.name, pool_price, deviation, reference_price
+            );
+            return Ok(Round::InRange);
+        }
+
+        let target_price =
+            pricing::target_price(pool_price, reference_price, self.pair.overshoot_bps);
+        let target = Ratio::from_price(target_price, self.token0.decimals, self.token1.decimals)
+            .ok_or_else(|| {
+                BotError::Unrepresentable(format!("Target price {} in raw units", target_price))
+            })?;
+        let Some(rebalance) = pricing::rebalance(reserve0, reserve1, target, self.pair.fee_bps)
+        else {
+            return Ok(Round::InRange);
+        };
+
+        let (input_token, output_token) = match rebalance.direction {
+            SwapDirection::Token1ToToken0 => (&self.token1, &self.token0),
+            SwapDirection::Token0ToToken1 => (&self.token0, &self.token1),
+        };
+        let input_balance = self.check_funds(input_token).await?;
+        record.direction = Some(rebalance.direction);
+        let Some(rebalance) = self.cap_trade(rebalance, input_token, input_balance)? else {
+            return Ok(Round::NothingToSell);
+        };
+        record.amount_in = Some(rebalance.amount_in);
+        let gas_estimate = self
+            .ctx
+            .gas
+            .estimate(&self.ctx.provider)
+            .await
+            .map_err(|source| match source.downcast::<GasPriceTooHighError>() {
+                Ok(too_high) => BotError::GasPriceTooHigh(too_high),
+                Err(source) => BotError::Rpc {
+                    call: "estimate gas fees",
+                    source,
+                },
+            })?;
+        self.ctx.gas.ensure_affordable(&gas_estimate)?;
+
+        self.report(KumaStatus::Up, "Pair is up").await;
+
+        self.ensure_allowance(input_token, input_balance).await?;
+        self.swap(
+            rebalance,
+            input_token,
+            output_token,
+            reference_price,
+            &gas_estimate,
+            record,
+        )
+        .await
+    }
+
+    /// Refuse to trade while the pair is halted for diverging too far from
+    /// the reference price
+    async fn check_breaker(
+        &self,
+        pool_price: Decimal,
+        reference_price: Decimal,
+        deviation_bps: Decimal,
+    ) -> Result<(), BotError> {
+        let Some(breaker) = &self.breaker else {
+            return Ok(());
+        };
+        let resume_generation = *self.ctx.resume.borrow();
+        let verdict = self.stats.record(|totals| {
+            breaker.check(
+                &mut totals.breaker,
+                deviation_bps,
+                Instant::now(),
+                resume_generation,
+            )
+        });
+        let halted = BotError::Halted {
+            pool_price,
+            reference_price,
+            deviation_bps,
+        };
+        match verdict {
+            Verdict::Trade => Ok(()),
+            Verdict::Tripped => {
+                warn!(
+                    "{}: circuit breaker tripped, trading resumes once the deviation stays below {} bps for {}s, or on SIGUSR1",
+                    self.pair.name,
+                    breaker.halt_deviation_bps,
+                    breaker.cooldown.as_secs()
+                );
+                Err(halted)
+            }
+            Verdict::Halted => Err(halted),
+            Verdict::Resumed(how) => {
+                let how = match how {
+                    Resumed::Manually => "manually",
+                    Resumed::AfterCooldown => "after cooldown",
+                };
+                let msg = format!("Trading resumed {}", how);
+                info!("{}: {}", self.pair.name, msg);
+                self.report(KumaStatus::Up, &msg).await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Check the wallet holds enough of the input token and of the gas coin,
+    /// returning the input token balance. Dry runs check the paper wallet,
+    /// seeding each balance from the real one on first use.
+    async fn check_funds(&self, input_token: &Token<P>) -> Result<U256, BotError> {
+        let wallet = self.ctx.wallet;
+        let input_balance = if self.ctx.dry_run {
+            let token = *input_token.contract.address();
+            match self.ctx.paper.balance(token) {
+                Some(balance) => balance,
+                None => {
+                    let balance = input_token
+                        .contract
+                        .balanceOf(wallet)
+                        .call()
+                        .await
+                        .map_err(BotError::rpc("get balance"))?;
+                    self.ctx.paper.seed(token, balance)
+                }
+            }
+        } else {
+            input_token
+                .contract
+                .balanceOf(wallet)
+                .call()
+                .await
+                .map_err(BotError::rpc("get balance"))?
+        };
+        if input_balance < input_token.min_balance {
+            let symbol = input_token
+                .contract
+                .symbol()
+                .call()
+                .await
+                .map_err(BotError::rpc("get symbol"))?;
+            return Err(BotError::InsufficientBalance { symbol, wallet });
+        }
+
+        let gas_balance = if self.ctx.dry_run {
+            match self.ctx.paper.native() {
+                Some(balance) => balance,
+                None => {
+                    let balance = self
+                        .ctx
+                        .provider
+                        .get_balance(wallet)
+                        .await
+                        .map_err(BotError::rpc("get gas balance"))?;
+                    self.ctx.paper.seed_native(balance)
+                }
+            }
+        } else {
+            self.ctx
+                .provider
+                .get_balance(wallet)
+                .await
+                .map_err(BotError::rpc("get gas balance"))?
+        };
+        if gas_balance < U256::from(MIN_GAS_BALANCE) {
+            return Err(BotError::InsufficientGas { wallet });
+        }
+        Ok(input_balance)
+    }
+
+    /// Shrink a rebalance to the pair's trade limits. Whatever is left of the
+    /// move is picked up by later rounds, as the pool still deviates.
+    /// Returns `None` if the limits leave nothing to sell.
+    fn cap_trade(
+        &self,
+        rebalance: Rebalance,
+        input_token: &Token<P>,
+        input_balance: U256,
+    ) -> Result<Option<Rebalance>, BotError> {
+        let now = Instant::now();
+        let (volume, frees_up_in) = self.stats.record(|totals| {
+            let window = totals.volume_mut(rebalance.direction);
+            (window.volume(now), window.frees_up_in(now))
+        });
+        let Some(max_input) =
+            capped_input(&input_token.limits, input_balance, volume, frees_up_in)?
+        else {
+            info!(
+                "{}: trade limits leave nothing to sell of the {} needed, skipping",
+                self.pair.name, rebalance.amount_in
+            );
+            return Ok(None);
+        };
+        if max_input >= rebalance.amount_in {
+            return Ok(Some(rebalance));
+        }
+        info!(
+            "{}: capping swap input at {} of {} needed, leaving the rest to later rounds",
+            self.pair.name, max_input, rebalance.amount_in
+        );
+        Ok(Some(Rebalance {
+            amount_in: max_input,
+            ..rebalance
+        }))
+    }
+
+    async fn ensure_allowance(
+        &self,
+        input_token: &Token<P>,
+        input_balance: U256,
+    ) -> Result<(), BotError> {
+        // Paper balances may exceed the real allowance, and a dry run never
+        // sends an approval
+        if self.ctx.dry_run {
+            return Ok(());
+        }
+        let router = *self.ctx.router.address();
+        let allowance = input_token
+            .contract
+            .allowance(self.ctx.wallet, router)
+            .call()
+            .await
+            .map_err(BotError::rpc("get allowance"))?;
+        if allowance >= input_balance {
+            return Ok(());
+        }
+
+        let approve = input_token
+            .contract
+            .approve(router, U256::MAX)
+            .into_transaction_request();
+        self.ensure_running()?;
+        let submitted = self
+            .ctx
+            .submitter
+            .submit(approve)
+            .await
+            .map_err(BotError::Approval)?;
+        info!("Approve tx hash: {}", submitted.tx_hash);
+        confirm(
+            &self.ctx.provider,
+            &self.ctx.submitter,
+            submitted,
+            &self.ctx.replacement_policy,
+            || async { true },
+        )
+        .await
+        .map_err(|e| BotError::Approval(e.into()))?;
+        Ok(())
+    }
+
+    async fn swap(
+        &self,
+        rebalance: Rebalance,
+        input_token: &Token<P>,
+        output_token: &Token<P>,
+        reference_price: Decimal,
+        gas_estimate: &GasEstimate,
+        record: &mut RoundRecord,
+    ) -> Result<Round, BotError> {
+        let input_amount = rebalance.amount_in;
+        let path = vec![
+            *input_token.contract.address(),
+            *output_token.contract.address(),
+        ];
+
+        let amounts = self
+            .ctx
+            .router
+            .getAmountsOut(input_amount, path.clone())
+            .call()
+            .await
+            .map_err(BotError::rpc("quote swap output"))?;
+        let expected_out = *amounts.last().ok_or_else(|| BotError::Rpc {
+            call: "quote swap output",
+            source: anyhow::anyhow!("empty getAmountsOut response"),
+        })?;
+        let amount_out_min = pricing::min_amount_out(expected_out, self.pair.slippage_bps);
+        record.expected_out = Some(expected_out);
+        record.min_out = Some(amount_out_min);
+
+        let block = self
+            .ctx
+            .provider
+            .get_block_by_number(BlockNumberOrTag::Latest)
+            .await
+            .map_err(BotError::rpc("fetch latest block"))?
+            .ok_or_else(|| BotError::Rpc {
+                call: "fetch latest block",
+                source: anyhow::anyhow!("latest block not found"),
+            })?;
+        let deadline = block.header.timestamp + self.ctx.swap_deadline_secs;
+
+        let swap_call = self
+            .ctx
+            .router
+            .swapExactTokensForTokens(
+                input_amount,
+                amount_out_min,
+                path,
+                self.ctx.wallet,
+                U256::from(deadline),
+            )
+            .from(self.ctx.wallet);
+
+        // Dry runs trade paper balances the real wallet may not hold or have
+        // approved, so they simulate with those overridden
+        let simulated_call = if self.ctx.dry_run {
+            self.ctx
+                .paper
+                .state_override(
+                    self.ctx.provider.clone(),
+                    *input_token.contract.address(),
+                    self.ctx.wallet,
+                    *self.ctx.router.address(),
+                )
+                .await
+                .map_err(BotError::rpc("locate token storage"))?
+                .map(|state| swap_call.clone().state(state))
+        } else {
+            Some(swap_call.clone())
+        };
+        // A reverting swap still costs gas, so only send it if it succeeds
+        // against the pending block
+        let gas_units = match simulated_call {
+            Some(call) => {
+                if let Err(e) = call.clone().block(BlockId::pending()).call().await {
+                    return Err(match SimulationError::from(e) {
+                        SimulationError::Reverted(reason) => BotError::SimulationReverted(reason),
+                        SimulationError::Rpc(e) => BotError::rpc("simulate swap")(e),
+                    });
+                }
+                call.estimate_gas()
+                    .await
+                    .map_err(BotError::rpc("estimate swap gas"))?
+            }
+            None => self.ctx.gas.gas_limit().unwrap_or(DRY_RUN_SWAP_GAS),
+        };
+        record.gas_units = Some(gas_units);
+        let gas_cost = U256::from(gas_units) * U256::from(gas_estimate.expected_gas_price());
+        let native_price0 = self.native_price0(reference_price).await?;
+        let amount_in = pricing::to_decimal(input_amount, input_token.decimals);
+        let profit = amount_in
+            .zip(pricing::to_decimal(expected_out, output_token.decimals))
+            .zip(pricing::to_decimal(gas_cost, self.ctx.native_decimals))
+            .and_then(|((amount_in, amount_out), gas_cost)| {
+                pricing::net_profit(
+                    rebalance.direction,
+                    amount_in,
+                    amount_out,
+                    reference_price,
+                    gas_cost.checked_mul(native_price0)?,
+                )
+            });
+        let (Some(amount_in), Some(profit)) = (amount_in, profit) else {
+            return Err(BotError::Unrepresentable("Swap value".to_string()));
+        };
+        record.expected_profit = Some(profit);
+        let min_profit = amount_in * Decimal::from(self.pair.min_profit_bps) / dec!(10000);
+        if profit < min_profit {
+            info!(
+                "{}: skipping swap, expected profit {} after {} gas is below {}",
+                self.pair.name, profit, gas_units, min_profit
+            );
+            return Ok(Round::Unprofitable);
+        }
+
+        if self.ctx.dry_run {
+            self.ctx.paper.apply(
+                *input_token.contract.address(),
+                input_amount,
+                *output_token.contract.address(),
+                expected_out,
+                gas_cost,
+            );
+            self.stats.record(|totals| {
+                totals.simulated_swaps += 1;
+                totals
+                    .volume_mut(rebalance.direction)
+                    .record(Instant::now(), input_amount);
+                if let Some(paper) = &mut totals.paper {
+                    paper.apply(rebalance.direction, input_amount, expected_out, gas_cost);
+                    totals.paper_pnl = paper.pnl(
+                        self.token0.decimals,
+                        self.token1.decimals,
+                        self.ctx.native_decimals,
+                        reference_price,
+                        native_price0,
+                    );
+                }
+            });
+            return Ok(Round::Simulated {
+                amount_in: input_amount,
+                expected_out,
+                gas_cost,
+            });
+        }
+
+        self.ensure_running()?;
+        let submitted = self
+            .ctx
+            .submitter
+            .submit(swap_call.clone().into_transaction_request())
+            .await
+            .map_err(BotError::Submission)?;
+        record.tx_hash = Some(submitted.tx_hash);
+        info!(
+            "Swap tx hash: {} (nonce {}, expected out {}, min out {})",
+            submitted.tx_hash, submitted.nonce, expected_out, amount_out_min
+        );
+
+        // A stuck swap is only worth pushing through while the pool still
+        // deviates from the reference and the swap would still go through in
+        // time. Otherwise it is cancelled.
+        let still_needed = || async {
+            if let Ok(Some(block)) = self
+                .ctx
+                .provider
+                .get_block_by_number(BlockNumberOrTag::Latest)
+                .await
+                && block.header.timestamp + REPLACEMENT_DEADLINE_MARGIN.as_secs() >= deadline
+            {
+                info!("{}: stuck swap is about to expire", self.pair.name);
+                return false;
+            }
+            // Against the latest block, as the pending one may already
+            // include the stuck swap itself
+            if let Err(e) = swap_call.clone().block(BlockId::latest()).call().await {
+                info!(
+                    "{}: stuck swap no longer simulates: {}",
+                    self.pair.name,
+                    SimulationError::from(e)
+                );
+                return false;
+            }
+            let (Ok((reserve0, reserve1)), Ok(quote)) =
+                (self.reserves().await, self.reference.quote().await)
+            else {
+                return true;
+            };
+            self.pool_price(reserve0, reserve1)
+                .and_then(|pool_price| pricing::deviation_bps(pool_price, quote.price))
+                .is_some_and(|deviation| {
+                    deviation >= Decimal::from(self.pair.deviation_threshold_bps)
+                })
+        };
+        let receipt = confirm(
+            &self.ctx.provider,
+            &self.ctx.submitter,
+            submitted,
+            &self.ctx.replacement_policy,
+            still_needed,
+        )
+        .await
+        .map_err(BotError::SwapFailed)?;
+
+        let Some(outcome) = decode_swap(
+            &receipt,
+            self.pair.pair_address,
+            rebalance.direction,
+            *input_token.contract.address(),
+            *output_token.contract.address(),
+            self.ctx.wallet,
+        ) else {
+            let gas_cost = U256::from(receipt.gas_used) * U256::from(receipt.effective_gas_price);
+            self.stats.record(|totals| totals.gas_cost += gas_cost);
+            return Err(BotError::SwapFailed(TxError::NoSwap {
+                tx_hash: receipt.transaction_hash,
+                block: receipt.block_number.unwrap_or_default(),
+            }));
+        };
+        self.stats.record(|totals| {
+            totals
+                .volume_mut(rebalance.direction)
+                .record(Instant::now(), outcome.amount_in)
+        });
+        Ok(Round::Swapped(outcome))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_source::{PriceQuote, PriceSource};
+    use async_trait::async_trait;
+    use std::time::SystemTime;
+
+    /// Quotes a fixed price observed `age` ago
+    struct AgedSource {
+        price: Decimal,
+        age: Duration,
+    }
+
+    #[async_trait]
+    impl PriceSource for AgedSource {
+        fn name(&self) -> &str {
+            "aged"
+        }
+
+        async fn price(&self, _pair: &str) -> anyhow::Result<PriceQuote> {
+            Ok(PriceQuote {
+                price: self.price,
+                timestamp: SystemTime::now() - self.age,
+            })
+        }
+    }
+
+    fn source(price: Decimal, age_secs: u64) -> ReferencePrice {
+        let source = AgedSource {
+            price,
+            age: Duration::from_secs(age_secs),
+        };
+        ReferencePrice::new(Arc::new(source), "ETHUSD", false)
+    }
+
+    #[test]
+    fn limits_rounding_to_zero_skip_the_trade() {
+        let window = Duration::from_secs(3600);
+        // A quarter of 3 wei rounds down to nothing
+        let dust = TradeLimits {
+            max_balance_fraction: Some(dec!(0.25)),
+            ..TradeLimits::default()
+        };
+        assert_eq!(
+            capped_input(&dust, U256::from(3), U256::ZERO, window).unwrap(),
+            None
+        );
+        assert_eq!(
+            capped_input(&dust, U256::from(400), U256::ZERO, window).unwrap(),
+            Some(U256::from(100))
+        );
+
+        // An exhausted volume cap is still an error, so the pair reports Down
+        let capped = TradeLimits {
+            max_daily_volume: Some(U256::from(500)),
+            ..dust
+        };
+        let error = capped_input(&capped, U256::from(400), U256::from(500), window).unwrap_err();
+        assert!(matches!(error, BotError::VolumeCapReached { resets_in } if resets_in == window));
+    }
+
+    #[tokio::test]
+    async fn stale_or_non_positive_quote_reports_down() {
+        let max_age = Duration::from_secs(60);
+        let price = checked_quote("reference", &source(dec!(2500), 5), max_age).await;
+        assert_eq!(price.unwrap(), dec!(2500));
+
+        let stale = checked_quote("reference", &source(dec!(2500), 120), max_age)
+            .await
+            .unwrap_err();
+        assert!(matches!(&stale, BotError::PriceRejected(e) if e.is::<StalePriceError>()));
+        assert!(stale.reports_down());
+
+        let zero = checked_quote("reference", &source(Decimal::ZERO, 5), max_age)
+            .await
+            .unwrap_err();
+        assert!(matches!(zero, BotError::PriceRejected(_)));
+        assert!(zero.reports_down());
+    }
+}